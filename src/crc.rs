@@ -0,0 +1,82 @@
+/**
+ * Incremental CRC-32 (IEEE 802.3, reflected polynomial 0xedb88320)
+ */
+#[derive(Clone, Copy)]
+pub struct Crc32(u32);
+
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb88320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self(0xffffffff)
+    }
+}
+
+impl Crc32 {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = TABLE[((self.0 ^ *byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    /// Feed `len` zero bytes
+    pub fn update_zeros(&mut self, len: usize) {
+        const ZEROS: [u8; 64] = [0; 64];
+        let mut left = len;
+        while left > 0 {
+            let chunk = left.min(ZEROS.len());
+            self.update(&ZEROS[.. chunk]);
+            left -= chunk;
+        }
+    }
+
+    pub fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/**
+ * Incremental 64-bit FNV-1a hash, for keys rather than error detection
+ */
+#[derive(Clone, Copy)]
+pub struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+}
+
+impl Fnv64 {
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    /// Feed a `usize` in little-endian, fixed width
+    pub fn update_usize(&mut self, value: usize) {
+        self.update(&(value as u64).to_le_bytes());
+    }
+
+    pub fn finish(self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,26 @@
+use super::*;
+
+#[test]
+fn crc32_check_value() {
+    let mut crc = Crc32::default();
+    crc.update(b"123456789");
+    assert_eq!(crc.finish(), 0xcbf43926);
+}
+
+#[test]
+fn crc32_zeros_match_explicit_bytes() {
+    let mut explicit = Crc32::default();
+    explicit.update(b"abc");
+    explicit.update(&[0; 200]);
+    let mut zeros = Crc32::default();
+    zeros.update(b"abc");
+    zeros.update_zeros(200);
+    assert_eq!(zeros.finish(), explicit.finish());
+}
+
+#[test]
+fn fnv64_check_value() {
+    let mut hash = Fnv64::default();
+    hash.update(b"a");
+    assert_eq!(hash.finish(), 0xaf63dc4c8601ec8c);
+}
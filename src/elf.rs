@@ -1,5 +1,6 @@
 use core::ops::Range;
-use alloc::collections::BTreeMap;
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use elf_rs::{ElfFile, ElfType, ProgramHeaderFlags, ProgramType};
 use enum_repr::EnumRepr;
 
 #[derive(Debug)]
@@ -10,23 +11,148 @@ pub struct Elf64RELA {
     pub addend: usize,
 }
 
+impl Elf64RELA {
+    /// Index of the referenced symbol, the upper half of `r_info`
+    pub fn sym_index(&self) -> usize {
+        self.info >> 32
+    }
+
+    /// Relocation type, the lower half of `r_info`, e.g. `R_RISCV_RELATIVE`
+    pub fn reloc_type(&self) -> usize {
+        self.info & 0xffffffff
+    }
+}
+
 #[repr(C)]
 pub struct Elf64REL {
     pub offset: usize,
     pub info: usize,
 }
 
+impl Elf64REL {
+    /// Relocation type, the lower half of `r_info`
+    pub fn reloc_type(&self) -> usize {
+        self.info & 0xffffffff
+    }
+}
+
+/// Bias of the TLS block offsets passed to `__tls_get_addr` on RISC-V
+pub const TLS_DTV_OFFSET: usize = 0x800;
+
+// RISC-V relocation types. S: symbol value, A: addend, P: address of the patched field, B: load bias
+
+/// No-op
+pub const R_RISCV_NONE: usize = 0;
+/// 32-bit word: S + A
+pub const R_RISCV_32: usize = 1;
+/// 64-bit word: S + A
+pub const R_RISCV_64: usize = 2;
+/// Adjust by the load bias: B + A
+pub const R_RISCV_RELATIVE: usize = 3;
+/// PLT slot: S
+pub const R_RISCV_JUMP_SLOT: usize = 5;
+/// TLS module id of the symbol's object
+pub const R_RISCV_TLS_DTPMOD64: usize = 7;
+/// Offset in the TLS block of the symbol's object: S + A - TLS_DTV_OFFSET
+pub const R_RISCV_TLS_DTPREL64: usize = 9;
+/// Offset from the thread pointer, for the static TLS model: S + A + TLS offset of the object
+pub const R_RISCV_TLS_TPREL64: usize = 11;
+/// 12-bit PC-relative branch offset (B-type): S + A - P
+pub const R_RISCV_BRANCH: usize = 16;
+/// 20-bit PC-relative jump offset (J-type): S + A - P
+pub const R_RISCV_JAL: usize = 17;
+/// auipc + jalr pair: S + A - P
+pub const R_RISCV_CALL: usize = 18;
+/// auipc + jalr pair through the PLT: S + A - P
+pub const R_RISCV_CALL_PLT: usize = 19;
+/// High 20 bits of a PC-relative offset (U-type): S + A - P
+pub const R_RISCV_PCREL_HI20: usize = 23;
+/// Low 12 bits of the PC-relative offset computed by the referenced HI20 (I-type)
+pub const R_RISCV_PCREL_LO12_I: usize = 24;
+/// Low 12 bits of the PC-relative offset computed by the referenced HI20 (S-type)
+pub const R_RISCV_PCREL_LO12_S: usize = 25;
+/// High 20 bits of an absolute address (U-type): S + A
+pub const R_RISCV_HI20: usize = 26;
+/// Low 12 bits of an absolute address (I-type): S + A
+pub const R_RISCV_LO12_I: usize = 27;
+/// Low 12 bits of an absolute address (S-type): S + A
+pub const R_RISCV_LO12_S: usize = 28;
+/// 8-bit in-place addition: V + S + A
+pub const R_RISCV_ADD8: usize = 33;
+/// 16-bit in-place addition: V + S + A
+pub const R_RISCV_ADD16: usize = 34;
+/// 32-bit in-place addition: V + S + A
+pub const R_RISCV_ADD32: usize = 35;
+/// 64-bit in-place addition: V + S + A
+pub const R_RISCV_ADD64: usize = 36;
+/// 8-bit in-place subtraction: V - S - A
+pub const R_RISCV_SUB8: usize = 37;
+/// 16-bit in-place subtraction: V - S - A
+pub const R_RISCV_SUB16: usize = 38;
+/// 32-bit in-place subtraction: V - S - A
+pub const R_RISCV_SUB32: usize = 39;
+/// 64-bit in-place subtraction: V - S - A
+pub const R_RISCV_SUB64: usize = 40;
+/// Alignment padding left for linker relaxation
+pub const R_RISCV_ALIGN: usize = 43;
+/// 8-bit PC-relative compressed branch offset (CB-type): S + A - P
+pub const R_RISCV_RVC_BRANCH: usize = 44;
+/// 11-bit PC-relative compressed jump offset (CJ-type): S + A - P
+pub const R_RISCV_RVC_JUMP: usize = 45;
+/// Marks the previous relocation as relaxable
+pub const R_RISCV_RELAX: usize = 51;
+/// Low 6 bits in-place subtraction: V - S - A
+pub const R_RISCV_SUB6: usize = 52;
+/// Low 6 bits: S + A
+pub const R_RISCV_SET6: usize = 53;
+/// 8-bit word: S + A
+pub const R_RISCV_SET8: usize = 54;
+/// 16-bit word: S + A
+pub const R_RISCV_SET16: usize = 55;
+/// 32-bit word: S + A
+pub const R_RISCV_SET32: usize = 56;
+/// 32-bit PC-relative word: S + A - P
+pub const R_RISCV_32_PCREL: usize = 57;
+
+#[allow(non_camel_case_types)]
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
 #[EnumRepr(type = "isize")]
 enum DynTag {
     DT_NULL = 0,
+    DT_NEEDED = 1,
+    DT_PLTRELSZ = 2,
+    DT_PLTGOT = 3,
+    DT_HASH = 4,
     DT_STRTAB = 5,
     DT_SYMTAB = 6,
     DT_RELA = 7,
     DT_RELASZ = 8,
     DT_RELAENT = 9,
+    DT_INIT = 12,
+    DT_FINI = 13,
+    DT_SONAME = 14,
+    DT_REL = 17,
+    DT_RELSZ = 18,
+    DT_RELENT = 19,
+    DT_PLTREL = 20,
+    DT_JMPREL = 23,
+    DT_INIT_ARRAY = 25,
+    DT_FINI_ARRAY = 26,
+    DT_INIT_ARRAYSZ = 27,
+    DT_FINI_ARRAYSZ = 28,
+    DT_FLAGS = 30,
+    DT_GNU_HASH = 0x6ffffef5,
+    DT_FLAGS_1 = 0x6ffffffb,
 }
 
+/// `DT_FLAGS`: resolve every relocation at load time
+pub const DF_BIND_NOW: usize = 0x8;
+/// `DT_FLAGS_1`: resolve every relocation at load time
+pub const DF_1_NOW: usize = 0x1;
+/// `DT_FLAGS_1`: the object is a position-independent executable
+pub const DF_1_PIE: usize = 0x08000000;
+
+#[allow(clippy::upper_case_acronyms)]
 pub enum RelTable<'a> {
     RELA(&'a [Elf64RELA]),
     REL(&'a [Elf64REL]),
@@ -43,10 +169,86 @@ pub struct Sym {
     pub size: u64,
 }
 
+/// Section index of undefined symbols
+pub const SHN_UNDEF: u16 = 0;
+/// Section index of absolute symbols
+pub const SHN_ABS: u16 = 0xfff1;
+/// Section index of common symbols, not yet allocated
+pub const SHN_COMMON: u16 = 0xfff2;
+
+/**
+ * Symbol binding, the upper half of `st_info`
+ */
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Local,
+    Global,
+    Weak,
+    Other(u8),
+}
+
+impl Sym {
+    pub fn binding(&self) -> Binding {
+        match self.info >> 4 {
+            0 => Binding::Local,
+            1 => Binding::Global,
+            2 => Binding::Weak,
+            b => Binding::Other(b),
+        }
+    }
+
+    /// Whether the symbol is defined in a real section of this object
+    pub fn is_defined(&self) -> bool {
+        self.shndx != SHN_UNDEF && self.shndx != SHN_ABS
+    }
+}
+
 pub struct Dynamic<'a> {
     pub rel: Option<RelTable<'a>>,
+    /// PLT relocations (`R_RISCV_JUMP_SLOT`), from DT_JMPREL and DT_PLTRELSZ
+    pub plt_rel: Option<&'a [Elf64RELA]>,
     pub dynsym: Option<&'a [Sym]>,
     pub dynstr: Option<&'a [u8]>,
+    /// Link-time vaddr of the GOT used by PLT entries (`.got.plt`), from DT_PLTGOT
+    pub pltgot: Option<usize>,
+    /// `DT_FLAGS` and `DT_FLAGS_1`, zero if absent
+    pub flags: usize,
+    pub flags_1: usize,
+    /// Number of `DT_NEEDED` entries, i.e. of shared libraries the object depends on
+    pub needed: usize,
+    /// Every `(tag, value)` of the dynamic array, up to `DT_NULL`
+    pub entries: Vec<(isize, usize)>,
+}
+
+/**
+ * Everything parsed from a dynamic array, for inspection tools. Addresses are link-time vaddrs.
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct DynamicInfo<'a> {
+    /// Every `(tag, value)` of the dynamic array, up to `DT_NULL`
+    pub entries: Vec<(isize, usize)>,
+
+    /// Names of the shared libraries depended on, and of this object
+    pub needed: Vec<&'a [u8]>,
+    pub soname: Option<&'a [u8]>,
+
+    /// Initialization and termination functions, and arrays of them as `(vaddr, size in bytes)`
+    pub init: Option<usize>,
+    pub fini: Option<usize>,
+    pub init_array: Option<(usize, usize)>,
+    pub fini_array: Option<(usize, usize)>,
+
+    /// Number of relocations, PLT ones included, and of dynamic symbols
+    pub relocations: usize,
+    pub symbols: usize,
+
+    pub pltgot: Option<usize>,
+    pub flags: usize,
+    pub flags_1: usize,
+
+    /// Presence of the SysV and GNU symbol hash tables
+    pub hash: bool,
+    pub gnu_hash: bool,
 }
 
 #[repr(C)]
@@ -55,36 +257,654 @@ struct DynEnt {
     val: usize,
 }
 
+/**
+ * Mapping from process vaddrs to file offsets, built from the file-backed part of PT_LOAD segments
+ */
+pub struct FileMap {
+    segments: Vec<(Range<usize>, usize)>,
+}
+
+impl FileMap {
+    pub fn new(phdrs: &[Phdr]) -> Self {
+        let segments = phdrs.iter()
+            .filter(|ph| ph.ty == ProgramType::LOAD)
+            .map(|ph| (ph.vaddr .. ph.vaddr.saturating_add(ph.filesz), ph.offset))
+            .collect();
+        Self { segments }
+    }
+
+    /// Translate a vaddr into its file offset, if it is backed by file content
+    pub fn offset_of(&self, vaddr: usize) -> Option<usize> {
+        self.segments.iter()
+            .find(|(range, _)| range.contains(&vaddr))
+            .and_then(|(range, offset)| offset.checked_add(vaddr - range.start))
+    }
+
+    /// Read the little-endian word stored in the file at `vaddr`
+    pub fn read_word(&self, elf: &[u8], vaddr: usize) -> Option<usize> {
+        read_u64(elf, self.offset_of(vaddr)?)
+    }
+}
+
+/**
+ * A program header
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Phdr {
+    pub ty: ProgramType,
+    pub flags: ProgramHeaderFlags,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+    pub align: usize,
+}
+
+impl Phdr {
+    /// File range of the segment content, `None` if its end overflows
+    pub fn file_range(&self) -> Option<Range<usize>> {
+        Some(self.offset .. self.offset.checked_add(self.filesz)?)
+    }
+
+    /// Link-time vaddr range of the segment in memory, `None` if its end overflows
+    pub fn mem_range(&self) -> Option<Range<usize>> {
+        Some(self.vaddr .. self.vaddr.checked_add(self.memsz)?)
+    }
+}
+
+/**
+ * The parts of the ELF header needed for loading by segments
+ */
+pub struct Header {
+    pub elf_type: ElfType,
+    /// Processor-specific flags (`e_flags`), e.g. the RISC-V float ABI
+    pub flags: u32,
+    pub entry: usize,
+    pub phoff: usize,
+    pub phdrs: Vec<Phdr>,
+}
+
+/// RVC instructions are used
+pub const EF_RISCV_RVC: u32 = 0x1;
+/// Mask of the floating-point calling convention
+pub const EF_RISCV_FLOAT_ABI: u32 = 0x6;
+/// RV32E/RV64E base ISA, with the reduced register set
+pub const EF_RISCV_RVE: u32 = 0x8;
+
+/**
+ * Name of the ELF64 RISC-V ABI encoded in `e_flags`, as passed to `-mabi`
+ */
+pub fn riscv_abi(flags: u32) -> &'static str {
+    if flags & EF_RISCV_RVE != 0 {
+        return "lp64e";
+    }
+    match (flags & EF_RISCV_FLOAT_ABI) >> 1 {
+        0 => "lp64",
+        1 => "lp64f",
+        2 => "lp64d",
+        _ => "lp64q",
+    }
+}
+
+/// GNU note types, under the `GNU` name
+pub const NT_GNU_ABI_TAG: u32 = 1;
+pub const NT_GNU_BUILD_ID: u32 = 3;
+pub const NT_GNU_PROPERTY_TYPE_0: u32 = 5;
+
+/**
+ * An entry of a PT_NOTE segment
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Note<'a> {
+    /// Owner of the note, e.g. `GNU`, without the terminating NUL
+    pub name: &'a [u8],
+
+    /// Type, interpreted according to `name`
+    pub ty: u32,
+
+    pub desc: &'a [u8],
+}
+
+/**
+ * Parse the entries of a note segment's content. Entries are padded to `align` bytes, 4 for
+ * most notes and 8 for e.g. GNU properties. Parsing stops at the first truncated entry.
+ */
+fn parse_notes(content: &[u8], align: usize) -> impl Iterator<Item = Note<'_>> {
+    let mut rest = content;
+    core::iter::from_fn(move || {
+        let namesz = read_u32(rest, 0)? as usize;
+        let descsz = read_u32(rest, 4)? as usize;
+        let ty = read_u32(rest, 8)?;
+        let desc_start = (12 + namesz).next_multiple_of(align);
+        let name = rest.get(12 .. 12 + namesz)?;
+        let desc = rest.get(desc_start .. desc_start + descsz)?;
+        rest = rest.get((desc_start + descsz).next_multiple_of(align) ..).unwrap_or(&[]);
+        Some(Note { name: name.strip_suffix(&[0]).unwrap_or(name), ty, desc })
+    })
+}
+
+/**
+ * Build attributes of a RISC-V object, from its `.riscv.attributes` section
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RiscvAttributes {
+    /// Exact ISA string, e.g. `rv64i2p1_m2p0_a2p1_f2p2_d2p2_c2p0`
+    pub arch: Option<String>,
+
+    /// Required stack alignment in bytes
+    pub stack_align: Option<usize>,
+}
+
+const TAG_FILE: u8 = 1;
+const TAG_RISCV_STACK_ALIGN: usize = 4;
+const TAG_RISCV_ARCH: usize = 5;
+
+impl RiscvAttributes {
+    /// Locate and parse the `.riscv.attributes` section of the ELF in `buf`
+    pub fn find(buf: &[u8]) -> Option<Self> {
+        if !has_section_headers(buf) {
+            return None;
+        }
+        let parsed = elf_rs::Elf64::from_bytes(buf).ok()?;
+        let sec_hdr = parsed.section_header_iter().find(|sec_hdr| sec_hdr.section_name() == b".riscv.attributes")?;
+        let offset = sec_hdr.offset() as usize;
+        Self::parse(buf.get(offset .. offset.checked_add(sec_hdr.size() as usize)?)?)
+    }
+
+    /**
+     * Parse the content of a `.riscv.attributes` section: a format version `A`, followed by
+     * length-prefixed vendor subsections. Only the file-wide attributes of the `riscv` vendor
+     * are read. Attributes with odd tags hold strings, even ones ULEB128 integers.
+     */
+    pub fn parse(section: &[u8]) -> Option<Self> {
+        let mut result = Self { arch: None, stack_align: None };
+        if section.first() != Some(&b'A') {
+            return None;
+        }
+
+        let mut sub = &section[1 ..];
+        while sub.len() >= 4 {
+            let len = read_u32(sub, 0)? as usize;
+            let (content, rest) = (sub.get(4 .. len)?, &sub[len ..]);
+            sub = rest;
+
+            let vendor_len = content.iter().position(|c| *c == 0)?;
+            if &content[.. vendor_len] != b"riscv" {
+                continue;
+            }
+
+            let mut subsub = &content[vendor_len + 1 ..];
+            while subsub.len() >= 5 {
+                let tag = subsub[0];
+                let len = read_u32(subsub, 1)? as usize;
+                let (mut attrs, rest) = (subsub.get(5 .. len)?, &subsub[len ..]);
+                subsub = rest;
+                if tag != TAG_FILE {
+                    continue;
+                }
+
+                while !attrs.is_empty() {
+                    let tag = read_uleb128(&mut attrs)?;
+                    if tag % 2 == 1 {
+                        let end = attrs.iter().position(|c| *c == 0)?;
+                        if tag == TAG_RISCV_ARCH {
+                            result.arch = Some(String::from(core::str::from_utf8(&attrs[.. end]).ok()?));
+                        }
+                        attrs = &attrs[end + 1 ..];
+                    } else {
+                        let value = read_uleb128(&mut attrs)?;
+                        if tag == TAG_RISCV_STACK_ALIGN {
+                            result.stack_align = Some(value);
+                        }
+                    }
+                }
+            }
+        }
+        Some(result)
+    }
+}
+
+/// Read an ULEB128 integer, advancing `buf` past it
+fn read_uleb128(buf: &mut &[u8]) -> Option<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let (byte, rest) = buf.split_first()?;
+        *buf = rest;
+        if shift < usize::BITS {
+            value |= ((byte & 0x7f) as usize) << shift;
+        }
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+}
+
+/// Range made read-only after relocation, not known to elf_rs
+pub const PT_GNU_RELRO: ProgramType = ProgramType::OsSpecific(0x6474e552);
+
+/// Thread-local storage template, not known to elf_rs
+pub const PT_TLS: ProgramType = ProgramType::Unknown(7);
+
+/// `EI_CLASS` of 64-bit objects
+pub const ELFCLASS64: u8 = 2;
+/// `EI_DATA` of little-endian objects
+pub const ELFDATA2LSB: u8 = 1;
+/// `e_machine` of RISC-V objects
+pub const EM_RISCV: u16 = 243;
+
+/**
+ * The kind of ELF files the loader accepts, and the paging parameters of the target
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct TargetSpec {
+    pub class: u8,
+    pub data: u8,
+    pub machine: u16,
+    pub page_size: usize,
+    /// Number of page table levels, e.g. 3 for Sv39
+    pub pt_levels: usize,
+}
+
+impl TargetSpec {
+    /// RV64 with Sv39 paging
+    pub const RV64: Self = Self { class: ELFCLASS64, data: ELFDATA2LSB, machine: EM_RISCV, page_size: 4096, pt_levels: 3 };
+
+    /// Whether the identification and machine of the ELF in `buf` match the target
+    pub fn matches(&self, buf: &[u8]) -> bool {
+        buf.get(0 .. 4) == Some(b"\x7fELF")
+            && buf.get(4) == Some(&self.class)
+            && buf.get(5) == Some(&self.data)
+            && read_u16(buf, 18) == Some(self.machine)
+    }
+}
+
+/// Target the loader is built for. Page sizes and table levels in `mem` are taken from it. Only
+/// RV64 is supported, as headers, symbols and relocations are all parsed in the ELF64 layout.
+pub const TARGET: TargetSpec = TargetSpec::RV64;
+
+/// Size of an ELF64 file header
+const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header
+const PHDR_SIZE: usize = 56;
+/// Size of an ELF64 section header
+const SHDR_SIZE: usize = 64;
+
+/**
+ * Interpret the low `bits` bits of `value` as a two's-complement integer, i.e. replicate bit
+ * `bits - 1` into every higher bit. `bits` must be within 1 ..= 64.
+ */
+pub fn sign_extend(value: u64, bits: u32) -> i64 {
+    let shift = 64 - bits;
+    ((value << shift) as i64) >> shift
+}
+
+/// Whether `value` is representable as a `bits`-bit two's-complement integer
+pub fn fits_signed(value: i64, bits: u32) -> bool {
+    sign_extend(value as u64, bits) == value
+}
+
+/// Whether the section header table of the ELF in `buf` lies within it, e.g. when only the
+/// headers at the start of the file are available
+pub fn has_section_headers(buf: &[u8]) -> bool {
+    let table = read_u64(buf, 0x28).zip(read_u16(buf, 0x3a)).zip(read_u16(buf, 0x3c));
+    table.and_then(|((shoff, shentsize), shnum)| shoff.checked_add(shentsize as usize * shnum as usize))
+        .is_some_and(|end| end <= buf.len())
+}
+
+fn read_u16(buf: &[u8], off: usize) -> Option<u16> {
+    buf.get(off .. off + 2).map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_u32(buf: &[u8], off: usize) -> Option<u32> {
+    buf.get(off .. off + 4).map(|b| u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn read_u64(buf: &[u8], off: usize) -> Option<usize> {
+    buf.get(off .. off + 8).map(|b| u64::from_le_bytes(b.try_into().unwrap()) as usize)
+}
+
+/**
+ * Link-time vaddr of the section named `name`, read straight from the section header table.
+ * Returns `None` if there is no such section, or if the table or its string table is malformed.
+ */
+pub fn section_vaddr(buf: &[u8], name: &[u8]) -> Option<usize> {
+    let shoff = read_u64(buf, 40)?;
+    let shentsize = read_u16(buf, 58)? as usize;
+    let shnum = read_u16(buf, 60)? as usize;
+    let shstrndx = read_u16(buf, 62)? as usize;
+    if shentsize < SHDR_SIZE {
+        return None;
+    }
+
+    let entry = |idx: usize| {
+        let base = shoff.checked_add(idx * shentsize)?;
+        buf.get(base .. base.checked_add(SHDR_SIZE)?)
+    };
+    let strtab = read_u64(entry(shstrndx)?, 24)?;
+    (0 .. shnum).find_map(|idx| {
+        let ent = entry(idx)?;
+        let name_start = strtab.checked_add(read_u32(ent, 0)? as usize)?;
+        let found = buf.get(name_start ..)?.split(|c| *c == 0).next()? == name;
+        found.then(|| read_u64(ent, 16)).flatten()
+    })
+}
+
+impl Header {
+    /**
+     * Parse the header through elf_rs. Fails if the file doesn't match `TARGET`, elf_rs rejects it,
+     * or the program header table doesn't fit in the buffer.
+     */
+    pub fn parse(buf: &[u8]) -> Option<Header> {
+        if !TARGET.matches(buf) {
+            return None;
+        }
+        let parsed = elf_rs::Elf64::from_bytes(buf).ok()?;
+        let ehdr = parsed.elf_header();
+        let phoff = ehdr.program_header_offset() as usize;
+        if phoff.checked_add(ehdr.program_header_entry_num() as usize * PHDR_SIZE)? > buf.len() {
+            return None;
+        }
+
+        let phdrs = parsed.program_header_iter()
+            .map(|ph| Phdr {
+                ty: ph.ph_type(),
+                flags: ph.flags(),
+                offset: ph.offset() as usize,
+                vaddr: ph.vaddr() as usize,
+                filesz: ph.filesz() as usize,
+                memsz: ph.memsz() as usize,
+                align: ph.align() as usize,
+            })
+            .collect();
+
+        Some(Header {
+            elf_type: ehdr.elftype(),
+            flags: ehdr.flags(),
+            entry: ehdr.entry_point() as usize,
+            phoff,
+            phdrs,
+        })
+    }
+
+    /**
+     * Minimal parser reading only the identification, type, entry and program headers, used when
+     * `parse` fails. Only the fields segment loading relies on are validated, so it tolerates:
+     * - `e_ehsize` not matching the actual header size
+     * - `e_phentsize` larger than the standard entry size (extra bytes of each entry are skipped)
+     * - a missing, truncated or malformed section header table, which is never read
+     *
+     * The file must still match `TARGET`, with a program header table inside the buffer.
+     */
+    pub fn parse_lenient(buf: &[u8]) -> Option<Header> {
+        if buf.len() < EHDR_SIZE || !TARGET.matches(buf) {
+            return None;
+        }
+
+        let phoff = read_u64(buf, 32)?;
+        let phentsize = read_u16(buf, 54)? as usize;
+        let phnum = read_u16(buf, 56)? as usize;
+        if phnum > 0 && phentsize < PHDR_SIZE {
+            return None;
+        }
+
+        let phdrs = (0 .. phnum)
+            .map(|idx| {
+                let base = phoff.checked_add(idx * phentsize)?;
+                Some(Phdr {
+                    ty: read_u32(buf, base)?.into(),
+                    flags: ProgramHeaderFlags::from_bits_truncate(read_u32(buf, base + 4)?),
+                    offset: read_u64(buf, base + 8)?,
+                    vaddr: read_u64(buf, base + 16)?,
+                    filesz: read_u64(buf, base + 32)?,
+                    memsz: read_u64(buf, base + 40)?,
+                    align: read_u64(buf, base + 48)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Header {
+            elf_type: read_u16(buf, 16)?.into(),
+            flags: read_u32(buf, 48)?,
+            entry: read_u64(buf, 24)?,
+            phoff,
+            phdrs,
+        })
+    }
+
+    /// The PT_TLS program header, if there is one
+    pub fn tls(&self) -> Option<&Phdr> {
+        self.phdrs.iter().find(|ph| ph.ty == PT_TLS)
+    }
+
+    /// The PT_GNU_RELRO segment, if there is one
+    pub fn relro(&self) -> Option<&Phdr> {
+        self.phdrs.iter().find(|ph| ph.ty == PT_GNU_RELRO)
+    }
+
+    /// Link-time vaddr of the program headers, e.g. for AT_PHDR. Taken from PT_PHDR, or else
+    /// derived from the PT_LOAD segment whose file content covers them.
+    pub fn phdr_vaddr(&self) -> Option<usize> {
+        if let Some(ph) = self.phdrs.iter().find(|ph| ph.ty == ProgramType::PHDR) {
+            return Some(ph.vaddr);
+        }
+        self.phdrs.iter()
+            .find(|ph| ph.ty == ProgramType::LOAD && ph.file_range().is_some_and(|range| range.contains(&self.phoff)))
+            .and_then(|ph| ph.vaddr.checked_add(self.phoff - ph.offset))
+    }
+
+    /// Entries of every PT_NOTE segment of the ELF in `buf`, e.g. the build-id and ABI tag
+    pub fn notes<'a, 'b: 'a>(&'a self, buf: &'b [u8]) -> impl Iterator<Item = Note<'b>> + 'a {
+        self.phdrs.iter()
+            .filter(|ph| ph.ty == ProgramType::NOTE)
+            .filter_map(|ph| Some((buf.get(ph.file_range()?)?, ph.align.max(4))))
+            .flat_map(|(content, align)| parse_notes(content, align))
+    }
+
+    /// Link-time vaddr of the dynamic array (`_DYNAMIC`), if there is one
+    pub fn dynamic_vaddr(&self) -> Option<usize> {
+        self.phdrs.iter()
+            .find(|ph| ph.ty == ProgramType::DYNAMIC)
+            .map(|ph| ph.vaddr)
+    }
+
+    /// File range of the dynamic array, if there is one. The range isn't checked against the
+    /// file, and its end saturates rather than wrapping, so `Dynamic::parse` rejects it.
+    pub fn dynamic(&self) -> Option<Range<usize>> {
+        self.phdrs.iter()
+            .find(|ph| ph.ty == ProgramType::DYNAMIC)
+            .map(|ph| ph.offset .. ph.offset.saturating_add(ph.filesz))
+    }
+}
+
 impl<'a> Dynamic<'a> {
-    pub fn parse(elf: &'a [u8], dynamic: Range<usize>) -> Self {
-        let (_, dynamic_region, _) = unsafe { elf[dynamic].align_to::<DynEnt>() };
+    /**
+     * Parse the dynamic array located at `dynamic` (file offsets). Addresses stored in the
+     * dynamic array are vaddrs, and are translated into file offsets through `map`.
+     * Returns `None` if the relocation table isn't exactly `DT_RELASZ / DT_RELAENT` entries of
+     * RELA size lying within the file (likewise for DT_REL), if the table isn't backed by the
+     * file of any segment, or
+     * if the dynamic array, symbol table or string table starts outside of the file.
+     */
+    pub fn parse(elf: &'a [u8], dynamic: Range<usize>, map: &FileMap) -> Option<Self> {
+        let (_, dynamic_region, _) = unsafe { elf.get(dynamic)?.align_to::<DynEnt>() };
         let collected: BTreeMap<DynTag, usize> = dynamic_region.iter().take_while(|e| e.tag != 0)
             .filter_map(|e| DynTag::from_repr(e.tag).map(|tag| (tag, e.val)))
             .collect();
 
-        let mut result = Self { rel: None, dynsym: None, dynstr: None };
-        if let Some(addr) = collected.get(&DynTag::DT_RELA) {
-            let sz = collected.get(&DynTag::DT_RELASZ).unwrap();
-            let ent = collected.get(&DynTag::DT_RELAENT).unwrap();
-            let rela = unsafe { core::slice::from_raw_parts(&elf[*addr] as *const u8 as *const Elf64RELA, *sz / *ent) };
+        let mut result = Self {
+            rel: None,
+            plt_rel: None,
+            dynsym: None,
+            dynstr: None,
+            pltgot: collected.get(&DynTag::DT_PLTGOT).copied(),
+            flags: collected.get(&DynTag::DT_FLAGS).copied().unwrap_or(0),
+            flags_1: collected.get(&DynTag::DT_FLAGS_1).copied().unwrap_or(0),
+            needed: dynamic_region.iter().take_while(|e| e.tag != 0).filter(|e| e.tag == DynTag::DT_NEEDED as isize).count(),
+            entries: dynamic_region.iter().take_while(|e| e.tag != 0).map(|e| (e.tag, e.val)).collect(),
+        };
+        let offset_of = |tag| collected.get(&tag).and_then(|addr| map.offset_of(*addr));
+
+        if let Some(vaddr) = collected.get(&DynTag::DT_RELA) {
+            let addr = map.offset_of(*vaddr)?;
+            let sz = *collected.get(&DynTag::DT_RELASZ)?;
+            let ent = *collected.get(&DynTag::DT_RELAENT)?;
+            if ent != core::mem::size_of::<Elf64RELA>() || sz % ent != 0 {
+                return None;
+            }
+            let table = elf.get(addr .. addr.checked_add(sz)?)?;
+            let rela = unsafe { core::slice::from_raw_parts(table.as_ptr() as *const Elf64RELA, sz / ent) };
             result.rel = Some(RelTable::RELA(rela));
+        } else if let Some(vaddr) = collected.get(&DynTag::DT_REL) {
+            // Not used on RISC-V, only parsed so that loading can reject it
+            let addr = map.offset_of(*vaddr)?;
+            let sz = *collected.get(&DynTag::DT_RELSZ)?;
+            let ent = *collected.get(&DynTag::DT_RELENT)?;
+            if ent != core::mem::size_of::<Elf64REL>() || sz % ent != 0 {
+                return None;
+            }
+            let table = elf.get(addr .. addr.checked_add(sz)?)?;
+            let rel = unsafe { core::slice::from_raw_parts(table.as_ptr() as *const Elf64REL, sz / ent) };
+            result.rel = Some(RelTable::REL(rel));
         }
 
-        if let Some(addr) = collected.get(&DynTag::DT_SYMTAB) {
-            result.dynsym = Some(unsafe { core::slice::from_raw_parts(&elf[*addr] as *const u8 as *const Sym, (elf.len() - addr) / core::mem::size_of::<Sym>()) });
+        // PLT relocations live in a table of their own, in the format given by DT_PLTREL.
+        // RISC-V only uses RELA.
+        if let Some(addr) = offset_of(DynTag::DT_JMPREL) {
+            let sz = collected.get(&DynTag::DT_PLTRELSZ).copied().unwrap_or(0);
+            let is_rela = collected.get(&DynTag::DT_PLTREL).is_none_or(|ty| *ty == DynTag::DT_RELA as usize);
+            if let Some(table) = elf.get(addr .. addr.saturating_add(sz)).filter(|_| is_rela) {
+                let count = table.len() / core::mem::size_of::<Elf64RELA>();
+                result.plt_rel = Some(unsafe { core::slice::from_raw_parts(table.as_ptr() as *const Elf64RELA, count) });
+            }
         }
 
-        if let Some(addr) = collected.get(&DynTag::DT_STRTAB) {
-            result.dynstr = Some(&elf[*addr..]);
+        if let Some(addr) = offset_of(DynTag::DT_SYMTAB) {
+            let table = elf.get(addr..)?;
+            let max_count = table.len() / core::mem::size_of::<Sym>();
+            let count = Self::sym_count(elf, &collected, map).unwrap_or(max_count).min(max_count);
+            result.dynsym = Some(unsafe { core::slice::from_raw_parts(table.as_ptr() as *const Sym, count) });
         }
 
-        result
+        if let Some(addr) = offset_of(DynTag::DT_STRTAB) {
+            result.dynstr = Some(elf.get(addr..)?);
+        }
+
+        Some(result)
     }
 
-    pub fn resolve_sym(&self, idx: usize) -> (&Sym, &[u8]) {
-        let sym = &self.dynsym.unwrap()[idx];
-        let str_start = &self.dynstr.unwrap()[sym.name as usize..];
+    /// Every RELA relocation of the object: those of DT_RELA, followed by the PLT ones
+    pub fn relas(&self) -> impl Iterator<Item = &'a Elf64RELA> + 'a {
+        let rela = match self.rel {
+            Some(RelTable::RELA(tbl)) => tbl,
+            _ => &[],
+        };
+        rela.iter().chain(self.plt_rel.unwrap_or(&[]))
+    }
+
+    /// Whether the object was linked with `-z now`, requiring every relocation to be resolved
+    /// when loading
+    pub fn bind_now(&self) -> bool {
+        self.flags & DF_BIND_NOW != 0 || self.flags_1 & DF_1_NOW != 0
+    }
+
+    /**
+     * Number of entries in the dynamic symbol table. The dynamic array doesn't record it directly,
+     * so it is recovered from the hash tables, falling back to the distance between the symbol
+     * table and the string table, which directly follows it in common layouts.
+     */
+    fn sym_count(elf: &[u8], collected: &BTreeMap<DynTag, usize>, map: &FileMap) -> Option<usize> {
+        let read_u32 = |off: usize| read_u32(elf, off).map(|v| v as usize);
+
+        if let Some(hash) = collected.get(&DynTag::DT_HASH).and_then(|addr| map.offset_of(*addr)) {
+            // nbucket, nchain, ...; nchain equals the number of symbols
+            return read_u32(hash + 4);
+        }
+
+        if let Some(hash) = collected.get(&DynTag::DT_GNU_HASH).and_then(|addr| map.offset_of(*addr)) {
+            // nbuckets, symoffset, bloom_size, bloom_shift, bloom[bloom_size], buckets[nbuckets], chain[]
+            let nbuckets = read_u32(hash)?;
+            let symoffset = read_u32(hash + 4)?;
+            let bloom_size = read_u32(hash + 8)?;
+            let buckets = hash + 16 + bloom_size * core::mem::size_of::<usize>();
+            let last = (0 .. nbuckets).filter_map(|i| read_u32(buckets + i * 4)).max()?;
+            if last < symoffset {
+                return Some(symoffset);
+            }
+
+            // Walk the chain of the last bucket until its terminating entry
+            let chain = buckets + nbuckets * 4;
+            let mut idx = last;
+            while read_u32(chain + (idx - symoffset) * 4)? & 1 == 0 {
+                idx += 1;
+            }
+            return Some(idx + 1);
+        }
+
+        let symtab = collected.get(&DynTag::DT_SYMTAB)?;
+        let strtab = collected.get(&DynTag::DT_STRTAB)?;
+        (strtab > symtab).then(|| (strtab - symtab) / core::mem::size_of::<Sym>())
+    }
+
+    /**
+     * Iterate over the symbols exported by this object: defined global or weak symbols, with
+     * their vaddr in the process address space after applying `bias`.
+     */
+    pub fn exports(&self, bias: usize) -> impl Iterator<Item = (&str, usize, Binding)> {
+        let count = self.dynsym.map_or(0, |syms| syms.len());
+        (0 .. count).filter_map(move |idx| {
+            let (sym, name) = self.resolve_sym(idx)?;
+            let binding = sym.binding();
+            if !sym.is_defined() || !matches!(binding, Binding::Global | Binding::Weak) {
+                return None;
+            }
+            let name = core::str::from_utf8(name).ok()?;
+            Some((name, bias + sym.value as usize, binding))
+        })
+    }
+
+    /// Gather everything parsed from the dynamic array
+    pub fn info(&self) -> DynamicInfo<'a> {
+        let get = |tag: DynTag| self.entries.iter().find(|(t, _)| *t == tag as isize).map(|(_, val)| *val);
+        let string = |offset: usize| {
+            let start = self.dynstr?.get(offset ..)?;
+            start.split(|e| *e == 0).next()
+        };
+        let array = |addr, size| get(addr).map(|vaddr| (vaddr, get(size).unwrap_or(0)));
+
+        DynamicInfo {
+            entries: self.entries.clone(),
+            needed: self.entries.iter()
+                .filter(|(tag, _)| *tag == DynTag::DT_NEEDED as isize)
+                .filter_map(|(_, offset)| string(*offset))
+                .collect(),
+            soname: get(DynTag::DT_SONAME).and_then(string),
+            init: get(DynTag::DT_INIT),
+            fini: get(DynTag::DT_FINI),
+            init_array: array(DynTag::DT_INIT_ARRAY, DynTag::DT_INIT_ARRAYSZ),
+            fini_array: array(DynTag::DT_FINI_ARRAY, DynTag::DT_FINI_ARRAYSZ),
+            relocations: match &self.rel {
+                Some(RelTable::REL(tbl)) => tbl.len(),
+                _ => self.relas().count(),
+            },
+            symbols: self.dynsym.map_or(0, |syms| syms.len()),
+            pltgot: self.pltgot,
+            flags: self.flags,
+            flags_1: self.flags_1,
+            hash: get(DynTag::DT_HASH).is_some(),
+            gnu_hash: get(DynTag::DT_GNU_HASH).is_some(),
+        }
+    }
+
+    /// Look up a dynamic symbol and its name by index
+    pub fn resolve_sym(&self, idx: usize) -> Option<(&'a Sym, &'a [u8])> {
+        let sym = self.dynsym?.get(idx)?;
+        let str_start = self.dynstr?.get(sym.name as usize..)?;
         let name = str_start.split(|e| *e == 0).next().unwrap();
-        (sym, name)
+        Some((sym, name))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,411 @@
+use super::*;
+use alloc::vec::Vec;
+use crate::testing::*;
+
+#[test]
+fn file_map_translates_file_backed_vaddrs() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x10200, PF_R, &[0; 0x100], 0x100);
+    builder.load(0x20000, PF_R | PF_W, &[0; 0x10], 0x1000);
+    let buf = builder.build();
+    let map = FileMap::new(&Header::parse(&buf).unwrap().phdrs);
+
+    assert_eq!(map.offset_of(0x10200), Some(0x1200));
+    assert_eq!(map.offset_of(0x102ff), Some(0x12ff));
+    assert_eq!(map.offset_of(0x10300), None);
+    assert_eq!(map.offset_of(0x20008), Some(0x2008));
+    // Zero-filled part of the segment
+    assert_eq!(map.offset_of(0x20010), None);
+}
+
+#[test]
+fn dynamic_parse_translates_table_vaddrs() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    // Place the dynamic tables far from their file offsets
+    builder.dyn_vaddr = Some(0x7_0000);
+    let sym = builder.sym(b"answer", 0x42, STB_GLOBAL, 1);
+    builder.rela(0x7_0100, 2, sym, 8);
+    let buf = builder.build();
+    let dynamic = parse_dynamic(&buf);
+
+    let (sym, name) = dynamic.resolve_sym(1).unwrap();
+    assert_eq!(name, b"answer");
+    assert_eq!(sym.value, 0x42);
+    match dynamic.rel {
+        Some(RelTable::RELA(table)) => {
+            assert_eq!(table.len(), 1);
+            assert_eq!((table[0].offset, table[0].info, table[0].addend), (0x7_0100, (1 << 32) | 2, 8));
+        }
+        _ => panic!("missing RELA table"),
+    }
+}
+
+fn parse_dynamic(buf: &[u8]) -> Dynamic<'_> {
+    let header = Header::parse(buf).unwrap();
+    Dynamic::parse(buf, header.dynamic().unwrap(), &FileMap::new(&header.phdrs)).unwrap()
+}
+
+#[test]
+fn exports_lists_defined_global_and_weak_symbols() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.sym(b"global", 0x100, STB_GLOBAL, SHN_TEXT);
+    builder.sym(b"weak", 0x200, STB_WEAK, SHN_TEXT);
+    builder.sym(b"local", 0x300, STB_LOCAL, SHN_TEXT);
+    builder.sym(b"undefined", 0, STB_GLOBAL, SHN_UNDEF);
+    builder.sym(b"absolute", 0x400, STB_GLOBAL, SHN_ABS);
+    let buf = builder.build();
+    let dynamic = parse_dynamic(&buf);
+
+    // Bounded by the string table following the symbol table
+    assert_eq!(dynamic.dynsym.unwrap().len(), 6);
+    let exports: Vec<_> = dynamic.exports(0x1000_0000).collect();
+    assert_eq!(exports, [("global", 0x1000_0100, Binding::Global), ("weak", 0x1000_0200, Binding::Weak)]);
+}
+
+#[test]
+fn sym_count_prefers_dt_hash() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.sym(b"a", 0x100, STB_GLOBAL, SHN_TEXT);
+    builder.sym(b"b", 0x200, STB_GLOBAL, SHN_TEXT);
+    // nbucket = 1, nchain = 2: the second symbol is left out
+    let hash: Vec<u8> = [1u32, 2, 0, 0, 0].iter().flat_map(|word| word.to_le_bytes()).collect();
+    builder.load(0x1000, PF_R, &hash, hash.len());
+    builder.dyn_entries.push((DT_HASH, 0x1000));
+    let buf = builder.build();
+    let dynamic = parse_dynamic(&buf);
+
+    assert_eq!(dynamic.dynsym.unwrap().len(), 2);
+    assert_eq!(dynamic.exports(0).map(|(name, ..)| name).collect::<Vec<_>>(), ["a"]);
+}
+
+/// An executable with text at 0x1000 and data at 0x2000
+fn two_segments() -> Vec<u8> {
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.entry = 0x1000;
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 0x100);
+    builder.build()
+}
+
+#[test]
+fn parse_lenient_ignores_header_size() {
+    let mut buf = two_segments();
+    buf[52 .. 54].copy_from_slice(&0xffffu16.to_le_bytes());
+    assert!(Header::parse(&buf).is_none());
+
+    let header = Header::parse_lenient(&buf).unwrap();
+    assert_eq!(header.elf_type, ElfType::ET_EXEC);
+    assert_eq!(header.entry, 0x1000);
+    assert_eq!(header.phdrs.len(), 2);
+    assert_eq!((header.phdrs[1].vaddr, header.phdrs[1].filesz, header.phdrs[1].memsz), (0x2000, 4, 0x100));
+    assert!(header.phdrs[1].flags.contains(ProgramHeaderFlags::WRITE));
+}
+
+#[test]
+fn parse_lenient_skips_padding_of_larger_entries() {
+    let mut buf = two_segments();
+    // Spread the program headers to 64-byte entries
+    let second: Vec<u8> = buf[120 .. 176].to_vec();
+    buf[128 .. 184].copy_from_slice(&second);
+    buf[54 .. 56].copy_from_slice(&64u16.to_le_bytes());
+
+    let header = Header::parse_lenient(&buf).unwrap();
+    assert_eq!(header.phdrs.iter().map(|ph| ph.vaddr).collect::<Vec<_>>(), [0x1000, 0x2000]);
+
+    buf[54 .. 56].copy_from_slice(&32u16.to_le_bytes());
+    assert!(Header::parse_lenient(&buf).is_none());
+}
+
+#[test]
+fn parse_rejects_program_headers_past_the_end() {
+    let mut buf = two_segments();
+    buf[56 .. 58].copy_from_slice(&200u16.to_le_bytes());
+    buf.truncate(0x1000);
+    assert!(Header::parse(&buf).is_none());
+    assert!(Header::parse_lenient(&buf).is_none());
+}
+
+#[test]
+fn section_vaddr_reads_section_headers() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x20], 0x20);
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1010, 0x10);
+    let buf = builder.build();
+    assert_eq!(section_vaddr(&buf, b".got"), Some(0x1010));
+    assert_eq!(section_vaddr(&buf, b".got.plt"), None);
+
+    // Out of bounds string table
+    let mut broken = buf.clone();
+    let shoff = u64::from_le_bytes(buf[40 .. 48].try_into().unwrap()) as usize;
+    let shstrndx = u16::from_le_bytes(buf[62 .. 64].try_into().unwrap()) as usize;
+    let at = shoff + shstrndx * 64 + 24;
+    broken[at .. at + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert_eq!(section_vaddr(&broken, b".got"), None);
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.section_headers = false;
+    assert_eq!(section_vaddr(&builder.build(), b".got"), None);
+}
+
+#[test]
+fn sign_extend_and_fits_signed() {
+    assert_eq!(sign_extend(0xfff, 12), -1);
+    assert_eq!(sign_extend(0x7ff, 12), 0x7ff);
+    assert_eq!(sign_extend(0x1_0000_0800, 12), -0x800);
+    assert_eq!(sign_extend(u64::MAX, 64), -1);
+    assert!(fits_signed(-0x800, 12));
+    assert!(!fits_signed(0x800, 12));
+    assert!(fits_signed(i64::MIN, 64));
+}
+
+/// `.riscv.attributes` content with a `riscv` vendor subsection holding `attrs`, after an
+/// unrelated vendor's
+fn attributes_section(attrs: &[u8]) -> Vec<u8> {
+    let mut section = alloc::vec![b'A'];
+    let other = b"gnu\0\x01";
+    section.extend_from_slice(&(4 + other.len() as u32).to_le_bytes());
+    section.extend_from_slice(other);
+
+    let mut riscv = b"riscv\0".to_vec();
+    riscv.push(1);
+    riscv.extend_from_slice(&(5 + attrs.len() as u32).to_le_bytes());
+    riscv.extend_from_slice(attrs);
+    section.extend_from_slice(&(4 + riscv.len() as u32).to_le_bytes());
+    section.extend_from_slice(&riscv);
+    section
+}
+
+#[test]
+fn riscv_attributes_parse() {
+    let section = attributes_section(b"\x04\x10\x05rv64imac\0\x06\x01");
+    let attributes = RiscvAttributes::parse(&section).unwrap();
+    assert_eq!(attributes.arch.as_deref(), Some("rv64imac"));
+    assert_eq!(attributes.stack_align, Some(16));
+
+    assert_eq!(RiscvAttributes::parse(b"B"), None);
+    // Truncated subsection
+    assert_eq!(RiscvAttributes::parse(&section[.. section.len() - 3]), None);
+}
+
+#[test]
+fn riscv_attributes_found_by_section_name() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R, b"text", 4);
+    builder.raw_section(b".riscv.attributes", 0x7000_0003, 0, &attributes_section(b"\x05rv64gc\0"));
+    let attributes = RiscvAttributes::find(&builder.build()).unwrap();
+    assert_eq!(attributes.arch.as_deref(), Some("rv64gc"));
+    assert_eq!(attributes.stack_align, None);
+}
+
+#[test]
+fn riscv_abi_from_flags() {
+    assert_eq!(riscv_abi(0), "lp64");
+    assert_eq!(riscv_abi(EF_RISCV_RVC | 0x4), "lp64d");
+    assert_eq!(riscv_abi(0x2), "lp64f");
+    assert_eq!(riscv_abi(EF_RISCV_RVE | 0x4), "lp64e");
+}
+
+#[test]
+fn phdr_vaddr_from_pt_phdr_or_covering_load() {
+    // The headers occupy the start of the file, at offset 0
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.phdr(PT_LOAD, PF_R, 0x40_0000, 0x1000, 0x1000);
+    let header = Header::parse(&builder.build()).unwrap();
+    assert_eq!(header.phdr_vaddr(), Some(0x40_0000 + header.phoff));
+
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.phdr(PT_LOAD, PF_R, 0x40_0000, 0x1000, 0x1000);
+    builder.phdr(PT_PHDR, PF_R, 0x50_0000, 0x100, 0x100);
+    assert_eq!(Header::parse(&builder.build()).unwrap().phdr_vaddr(), Some(0x50_0000));
+
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x1000, PF_R, b"text", 4);
+    assert_eq!(Header::parse(&builder.build()).unwrap().phdr_vaddr(), None);
+}
+
+#[test]
+fn bind_now_from_either_flags_entry() {
+    for (tag, val, now) in [(DT_FLAGS, DF_BIND_NOW, true), (DT_FLAGS_1, DF_1_NOW, true), (DT_FLAGS, 0x2, false)] {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.dyn_entries.push((tag, val));
+        let buf = builder.build();
+        assert_eq!(parse_dynamic(&buf).bind_now(), now);
+    }
+}
+
+#[test]
+fn plt_relocations_follow_rela() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    let sym = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    builder.plt_rela(0x3008, R_RISCV_JUMP_SLOT, sym, 0);
+    builder.plt_rela(0x3010, R_RISCV_JUMP_SLOT, sym, 0);
+    let buf = builder.build();
+
+    let dynamic = parse_dynamic(&buf);
+    assert_eq!(dynamic.plt_rel.map(<[_]>::len), Some(2));
+    let offsets: Vec<_> = dynamic.relas().map(|ent| ent.offset).collect();
+    assert_eq!(offsets, [0x3000, 0x3008, 0x3010]);
+}
+
+#[test]
+fn plt_relocations_ignored_unless_rela() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.plt_rela(0x3008, R_RISCV_JUMP_SLOT, 0, 0);
+    // DT_REL
+    builder.dyn_entries.push((DT_PLTREL, 17));
+    let buf = builder.build();
+    assert!(parse_dynamic(&buf).plt_rel.is_none());
+}
+
+#[test]
+fn target_rejects_other_class_and_machine() {
+    let mut buf = ElfBuilder::new(ET_DYN).build();
+    assert!(TARGET.matches(&buf));
+    assert!(Header::parse(&buf).is_some());
+
+    // ELFCLASS32
+    let mut elf32 = buf.clone();
+    elf32[4] = 1;
+    assert!(!TARGET.matches(&elf32));
+    assert!(Header::parse(&elf32).is_none());
+    assert!(Header::parse_lenient(&elf32).is_none());
+
+    // x86-64
+    buf[18 .. 20].copy_from_slice(&62u16.to_le_bytes());
+    assert!(!TARGET.matches(&buf));
+    assert!(Header::parse(&buf).is_none());
+    assert!(Header::parse_lenient(&buf).is_none());
+}
+
+#[test]
+fn rela_info_split_into_symbol_and_type() {
+    let ent = Elf64RELA { offset: 0x3000, info: (7 << 32) | R_RISCV_JUMP_SLOT, addend: 0 };
+    assert_eq!(ent.sym_index(), 7);
+    assert_eq!(ent.reloc_type(), R_RISCV_JUMP_SLOT);
+}
+
+/// Note entry padded to 4 bytes
+fn note(name: &[u8], ty: u32, desc: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&(name.len() as u32 + 1).to_le_bytes());
+    out.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+    out.extend_from_slice(&ty.to_le_bytes());
+    out.extend_from_slice(name);
+    out.push(0);
+    out.resize(out.len().next_multiple_of(4), 0);
+    out.extend_from_slice(desc);
+    out.resize(out.len().next_multiple_of(4), 0);
+    out
+}
+
+#[test]
+fn notes_iterated_with_types() {
+    let mut content = note(b"GNU", NT_GNU_BUILD_ID, &[0xab; 20]);
+    content.extend(note(b"GNU", NT_GNU_ABI_TAG, &[0, 0, 0, 0, 3, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]));
+    content.extend(note(b"Vendor", 0x42, b"xyz"));
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R, &content, content.len());
+    builder.phdr(PT_NOTE, PF_R, 0x1000, content.len(), content.len()).align = 4;
+    let buf = builder.build();
+    let header = Header::parse(&buf).unwrap();
+
+    let notes: Vec<_> = header.notes(&buf).collect();
+    assert_eq!(notes.len(), 3);
+    assert_eq!((notes[0].name, notes[0].ty, notes[0].desc), (b"GNU" as &[u8], NT_GNU_BUILD_ID, &[0xabu8; 20] as &[u8]));
+    assert_eq!((notes[1].name, notes[1].ty, notes[1].desc.len()), (b"GNU" as &[u8], NT_GNU_ABI_TAG, 16));
+    assert_eq!((notes[2].name, notes[2].ty, notes[2].desc), (b"Vendor" as &[u8], 0x42, b"xyz" as &[u8]));
+}
+
+#[test]
+fn dynamic_info_gathers_parsed_entries() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x20], 0x20);
+    let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    builder.rela(0x1000, R_RISCV_RELATIVE, 0, 0x10);
+    builder.plt_rela(0x1008, R_RISCV_JUMP_SLOT, puts, 0);
+    let libc = builder.string(b"libc.so.6");
+    let soname = builder.string(b"libfoo.so");
+    builder.dyn_entries.extend([(DT_NEEDED, libc), (DT_SONAME, soname), (DT_INIT, 0x1100), (DT_INIT_ARRAY, 0x1010), (DT_INIT_ARRAYSZ, 16), (DT_FLAGS, DF_BIND_NOW)]);
+    let buf = builder.build();
+    let info = parse_dynamic(&buf).info();
+
+    assert_eq!(info.needed, [b"libc.so.6" as &[u8]]);
+    assert_eq!(info.soname, Some(b"libfoo.so" as &[u8]));
+    assert_eq!((info.init, info.fini), (Some(0x1100), None));
+    assert_eq!((info.init_array, info.fini_array), (Some((0x1010, 16)), None));
+    assert_eq!(info.relocations, 2);
+    assert_eq!(info.symbols, 2);
+    assert_eq!((info.flags, info.flags_1), (DF_BIND_NOW, 0));
+    assert!(!info.hash && !info.gnu_hash);
+    assert!(info.entries.contains(&(DT_SONAME, soname)));
+}
+
+#[test]
+fn rela_outside_segments_rejected() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    builder.dyn_entries.push((DT_RELA, 0x9000));
+    let buf = builder.build();
+    let header = Header::parse(&buf).unwrap();
+    assert!(Dynamic::parse(&buf, header.dynamic().unwrap(), &FileMap::new(&header.phdrs)).is_none());
+}
+
+#[test]
+fn dynamic_parse_rejects_tables_outside_of_file() {
+    let header_with = |tag| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.dyn_entries.push((tag, 0x9_0000));
+        builder.build()
+    };
+    for tag in [DT_SYMTAB, DT_STRTAB] {
+        let buf = header_with(tag);
+        let header = Header::parse(&buf).unwrap();
+        // A segment claiming file content past the end of the file
+        let mut phdrs = header.phdrs.clone();
+        let mut beyond = *phdrs.iter().find(|ph| ph.ty == ProgramType::LOAD).unwrap();
+        (beyond.vaddr, beyond.offset, beyond.filesz) = (0x9_0000, buf.len() + 0x100, 0x1000);
+        phdrs.push(beyond);
+        assert!(Dynamic::parse(&buf, header.dynamic().unwrap(), &FileMap::new(&phdrs)).is_none());
+    }
+
+    let buf = header_with(DT_FLAGS);
+    let header = Header::parse(&buf).unwrap();
+    let map = FileMap::new(&header.phdrs);
+    let dynamic = header.dynamic().unwrap();
+    assert!(Dynamic::parse(&buf, dynamic.start .. buf.len() + 1, &map).is_none());
+    assert!(Dynamic::parse(&buf, usize::MAX - 8 .. usize::MAX, &map).is_none());
+}
+
+#[test]
+fn overflowing_dynamic_segment_saturates() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 16], 16);
+    builder.phdr(PT_DYNAMIC, PF_R | PF_W, 0x1000, usize::MAX, 16);
+    let buf = builder.build();
+    let header = Header::parse(&buf).unwrap();
+    assert_eq!(header.dynamic().unwrap().end, usize::MAX);
+    assert!(Dynamic::parse(&buf, header.dynamic().unwrap(), &FileMap::new(&header.phdrs)).is_none());
+}
+
+#[test]
+fn overflowing_header_fields_rejected() {
+    // Program headers covered by a segment whose end or vaddr overflows
+    for (vaddr, filesz) in [(0x1000, usize::MAX), (usize::MAX - 8, 0x100)] {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        let ph = builder.load(0x1000, PF_R, b"x", 1);
+        (ph.vaddr, ph.offset, ph.filesz) = (vaddr, 0x10, filesz);
+        let buf = builder.build();
+        assert_eq!(Header::parse(&buf).unwrap().phdr_vaddr(), None);
+    }
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.raw_section(b".riscv.attributes", 0x7000_0003, 0, &attributes_section(b"\x05rv64gc\0"));
+    builder.sections.last_mut().unwrap().size = usize::MAX;
+    assert!(RiscvAttributes::find(&builder.build()).is_none());
+
+    let mut buf = ElfBuilder::new(ET_DYN).build();
+    buf[32 .. 40].copy_from_slice(&u64::MAX.to_le_bytes());
+    assert!(Header::parse(&buf).is_none());
+}
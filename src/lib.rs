@@ -1,8 +1,14 @@
-#![feature(adt_const_params)]
-#[no_std]
+#![no_std]
 
 extern crate alloc;
 
-mod elf;
+mod crc;
+pub mod elf;
+mod linker;
 mod mem;
-pub mod loader;
\ No newline at end of file
+pub mod loader;
+#[cfg(feature = "std")]
+pub mod trace;
+
+#[cfg(test)]
+mod testing;
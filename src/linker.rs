@@ -0,0 +1,452 @@
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use elf_rs::{Elf64, ElfFile, SectionHeaderFlags, SectionHeaderWrapper, SectionType};
+
+use crate::{elf::*, loader::{LoadError, MMU, Perm, RelocIssue}, mem::VirtAddr};
+
+/**
+ * A resolved relocation, waiting to be written into the process address space
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Fixup {
+    pub vaddr: usize,
+    pub value: usize,
+
+    /// Value the static linker may have left in the slot besides zero, if predictable
+    pub expected: Option<usize>,
+
+    /// Size of the slot in bytes: 1, 2, 4 or 8. `value` is truncated to it.
+    pub width: usize,
+}
+
+impl Fixup {
+    /// Relocation of a whole word
+    pub fn word(vaddr: usize, value: usize, expected: Option<usize>) -> Self {
+        Self { vaddr, value, expected, width: core::mem::size_of::<usize>() }
+    }
+
+    pub fn apply<M: MMU>(&self, mmu: &mut M) {
+        let paddr = mmu.translate(self.vaddr).unwrap();
+        unsafe { write_field(paddr, self.width, self.value as u64) };
+    }
+
+    /// Whether the slot still holds a value consistent with the relocation, before applying it
+    pub fn check<M: MMU>(&self, mmu: &M) -> bool {
+        let paddr = mmu.translate(self.vaddr).unwrap();
+        let current = unsafe { read_field(paddr, self.width) };
+        let mask = u64::MAX >> (64 - 8 * self.width);
+        self.expected.is_none_or(|expected| current == 0 || current == expected as u64 & mask)
+    }
+}
+
+/// Size in bytes of the field patched by a dynamic relocation type
+pub fn reloc_width(ty: usize) -> usize {
+    match ty {
+        R_RISCV_SET8 => 1,
+        R_RISCV_SET16 => 2,
+        R_RISCV_32 | R_RISCV_SET32 => 4,
+        _ => core::mem::size_of::<usize>(),
+    }
+}
+
+/// Read a little-endian field of `width` bytes at `paddr`, which may be unaligned
+pub unsafe fn read_field(paddr: usize, width: usize) -> u64 {
+    match width {
+        1 => (paddr as *const u8).read() as u64,
+        2 => u16::from_le((paddr as *const u16).read_unaligned()) as u64,
+        4 => u32::from_le((paddr as *const u32).read_unaligned()) as u64,
+        _ => u64::from_le((paddr as *const u64).read_unaligned()),
+    }
+}
+
+/// Write the low `width` bytes of `value` in little-endian at `paddr`, which may be unaligned,
+/// leaving the bytes around untouched
+pub unsafe fn write_field(paddr: usize, width: usize, value: u64) {
+    match width {
+        1 => (paddr as *mut u8).write(value as u8),
+        2 => (paddr as *mut u16).write_unaligned((value as u16).to_le()),
+        4 => (paddr as *mut u32).write_unaligned((value as u32).to_le()),
+        _ => (paddr as *mut u64).write_unaligned(value.to_le()),
+    }
+}
+
+/**
+ * Compute a dynamic relocation of an object loaded with `bias`. Symbols defined by the object resolve
+ * to their own relocated address, undefined ones to the vaddr returned by `resolve`. TLS
+ * relocations resolve within the TLS block of the object, TLS module `tls_module`.
+ * Returns `None` for relocations with nothing to write.
+ */
+pub fn relocate_dynamic<F: FnMut(&[u8]) -> Option<usize>>(ent: &Elf64RELA, dynamic: &Dynamic, bias: usize, tls_module: usize, resolve: &mut F) -> Result<Option<Fixup>, RelocIssue> {
+    let vaddr = bias + ent.offset;
+    // Linkers either leave absolute slots zeroed or store the addend in them. PLT slots point
+    // at the PLT header instead, so they are not predictable.
+    let (value, expected) = match ent.reloc_type() {
+        R_RISCV_NONE => return Ok(None),
+        R_RISCV_RELATIVE => (bias.wrapping_add(ent.addend), Some(ent.addend)),
+        R_RISCV_64 => (dynamic_symbol(ent, dynamic, bias, resolve)?.wrapping_add(ent.addend), Some(ent.addend)),
+        R_RISCV_JUMP_SLOT => (dynamic_symbol(ent, dynamic, bias, resolve)?, None),
+        R_RISCV_32 | R_RISCV_SET32 | R_RISCV_SET16 | R_RISCV_SET8 => (dynamic_symbol(ent, dynamic, bias, resolve)?.wrapping_add(ent.addend), Some(ent.addend)),
+        // TLS symbols are only resolved within the object's own block, module `tls_module`,
+        // which only has a known static TLS offset, zero, for the main program
+        R_RISCV_TLS_DTPMOD64 => {
+            tls_symbol(ent, dynamic, bias)?;
+            (tls_module, None)
+        },
+        R_RISCV_TLS_DTPREL64 => (tls_symbol(ent, dynamic, bias)?.wrapping_add(ent.addend).wrapping_sub(TLS_DTV_OFFSET), None),
+        R_RISCV_TLS_TPREL64 if tls_module == 1 => (tls_symbol(ent, dynamic, bias)?.wrapping_add(ent.addend), Some(ent.addend)),
+        ty => return Err(RelocIssue::Unsupported { offset: vaddr, ty }),
+    };
+
+    // Sub-word fields may hold the value either signed or unsigned
+    let width = reloc_width(ent.reloc_type());
+    let bits = 8 * width as u32;
+    if bits < usize::BITS && !fits_signed(value as i64, bits) && value >> bits != 0 {
+        return Err(RelocIssue::Overflow { offset: vaddr, ty: ent.reloc_type() });
+    }
+    Ok(Some(Fixup { vaddr, value, expected, width }))
+}
+
+/// Offset of the symbol of a TLS relocation in the TLS block of the object. Undefined symbols
+/// live in the block of another object, and symbol 0 stands for the start of the block.
+fn tls_symbol(ent: &Elf64RELA, dynamic: &Dynamic, bias: usize) -> Result<usize, RelocIssue> {
+    let offset = bias + ent.offset;
+    if ent.sym_index() == 0 {
+        return Ok(0);
+    }
+    let (sym, name) = dynamic.resolve_sym(ent.sym_index())
+        .ok_or(RelocIssue::Unresolved { offset, name: Vec::new() })?;
+    match sym.shndx {
+        SHN_UNDEF => Err(RelocIssue::Unresolved { offset, name: name.to_vec() }),
+        _ => Ok(sym.value as usize),
+    }
+}
+
+fn dynamic_symbol<F: FnMut(&[u8]) -> Option<usize>>(ent: &Elf64RELA, dynamic: &Dynamic, bias: usize, resolve: &mut F) -> Result<usize, RelocIssue> {
+    let offset = bias + ent.offset;
+    let (sym, name) = dynamic.resolve_sym(ent.sym_index())
+        .ok_or(RelocIssue::Unresolved { offset, name: Vec::new() })?;
+
+    match sym.shndx {
+        SHN_ABS => Ok(sym.value as usize),
+        SHN_UNDEF => match resolve(name) {
+            Some(addr) => Ok(addr),
+            // Undefined weak symbols resolve to zero
+            None if sym.binding() == Binding::Weak => Ok(0),
+            None => Err(RelocIssue::Unresolved { offset, name: name.to_vec() }),
+        },
+        _ => Ok(bias + sym.value as usize),
+    }
+}
+
+/**
+ * Name of the symbol referenced by a dynamic relocation if it is undefined and unknown to
+ * `resolve`, and whether it is weak, in which case it resolves to zero instead of failing.
+ * Symbols with a bad index have an empty name.
+ */
+pub fn unresolved_symbol<'a, F: FnMut(&[u8]) -> Option<usize>>(ent: &Elf64RELA, dynamic: &Dynamic<'a>, resolve: &mut F) -> Option<(&'a [u8], bool)> {
+    if !matches!(ent.reloc_type(), R_RISCV_64 | R_RISCV_JUMP_SLOT | R_RISCV_32 | R_RISCV_SET32 | R_RISCV_SET16 | R_RISCV_SET8) {
+        return None;
+    }
+    let Some((sym, name)) = dynamic.resolve_sym(ent.sym_index()) else { return Some((&[], false)) };
+    (sym.shndx == SHN_UNDEF && resolve(name).is_none()).then_some((name, sym.binding() == Binding::Weak))
+}
+
+/**
+ * A contiguous part of a linked relocatable object, ready to be mapped
+ */
+pub struct LinkedRegion {
+    /// Start of the region in process address space, page-aligned
+    pub vaddr: usize,
+
+    /// Mapping permission
+    pub perm: Perm,
+
+    /// Relocated content, including zero-filled NOBITS sections
+    pub content: Vec<u8>,
+}
+
+/// Where an allocated section or common symbol ended up: the region holding it and its offset inside
+#[derive(Clone, Copy)]
+struct Placement {
+    region: usize,
+    offset: usize,
+}
+
+/// Permission classes of linked regions, in placement order
+const CLASSES: [Perm; 3] = [
+    Perm { r: true, w: false, x: true },
+    Perm { r: true, w: false, x: false },
+    Perm { r: true, w: true, x: false },
+];
+
+fn section_perm(sec: &SectionHeaderWrapper) -> Perm {
+    Perm {
+        r: true,
+        w: sec.flags().contains(SectionHeaderFlags::SHF_WRITE),
+        x: sec.flags().contains(SectionHeaderFlags::SHF_EXECINSTR),
+    }
+}
+
+fn section_content<'a>(buf: &'a [u8], sec: &SectionHeaderWrapper) -> Result<&'a [u8], LoadError> {
+    let offset = sec.offset() as usize;
+    offset.checked_add(sec.size() as usize)
+        .and_then(|end| buf.get(offset .. end))
+        .ok_or(LoadError::InvalidElf)
+}
+
+/**
+ * Link a relocatable object (ET_REL) to be placed at `base`, resolving undefined symbols through `lookup`.
+ *
+ * Linking is done in two passes: all SHF_ALLOC sections are first assigned an address and the whole
+ * symbol table is computed, and only then relocations are applied. This way, relocations can
+ * reference symbols defined in sections placed after the one being relocated.
+ */
+pub fn link_relocatable<F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], parsed: &Elf64, base: usize, lookup: &mut F) -> Result<Vec<LinkedRegion>, LoadError> {
+    let symtab_hdr = parsed.section_header_iter()
+        .find(|sec| sec.sh_type() == SectionType::SHT_SYMTAB)
+        .ok_or(LoadError::InvalidElf)?;
+    let strtab = parsed.section_header_nth(symtab_hdr.link() as usize)
+        .ok_or(LoadError::InvalidElf)
+        .and_then(|sec| section_content(buf, &sec))?;
+    let symtab = section_content(buf, &symtab_hdr)?;
+    let syms = unsafe { core::slice::from_raw_parts(symtab.as_ptr() as *const Sym, symtab.len() / core::mem::size_of::<Sym>()) };
+
+    // Pass 1: lay out sections, grouping them by permission into page-aligned regions.
+    // Common symbols are allocated at the end of the writable region.
+    let section_count = parsed.elf_header().section_header_entry_num() as usize;
+    let mut sections: Vec<Option<Placement>> = vec![None; section_count];
+    let mut commons: BTreeMap<usize, Placement> = BTreeMap::new();
+    let mut regions = Vec::new();
+    let mut cursor = base;
+    for perm in CLASSES {
+        let mut content = Vec::new();
+        for (idx, sec) in parsed.section_header_iter().enumerate() {
+            if !sec.flags().contains(SectionHeaderFlags::SHF_ALLOC) || section_perm(&sec) != perm {
+                continue;
+            }
+
+            let offset = content.len().next_multiple_of((sec.addralign() as usize).max(1));
+            content.resize(offset, 0);
+            if sec.sh_type() == SectionType::SHT_NOBITS {
+                content.resize(offset + sec.size() as usize, 0);
+            } else {
+                content.extend_from_slice(section_content(buf, &sec)?);
+            }
+            sections[idx] = Some(Placement { region: regions.len(), offset });
+        }
+
+        if perm.w {
+            for (idx, sym) in syms.iter().enumerate() {
+                if sym.shndx == SHN_COMMON {
+                    // For common symbols, the value holds the alignment
+                    let offset = content.len().next_multiple_of((sym.value as usize).max(1));
+                    content.resize(offset + sym.size as usize, 0);
+                    commons.insert(idx, Placement { region: regions.len(), offset });
+                }
+            }
+        }
+
+        if content.is_empty() {
+            continue;
+        }
+
+        let vaddr = cursor;
+        cursor = VirtAddr::from(VirtAddr(vaddr + content.len()).ceil()).0;
+        regions.push(LinkedRegion { vaddr, perm, content });
+    }
+
+    let addr_of = |regions: &[LinkedRegion], placement: &Placement| regions[placement.region].vaddr + placement.offset;
+    let mut values = Vec::with_capacity(syms.len());
+    for (idx, sym) in syms.iter().enumerate() {
+        let value = match sym.shndx {
+            SHN_UNDEF if sym.name == 0 => 0,
+            SHN_UNDEF => {
+                let name = strtab.get(sym.name as usize ..).ok_or(LoadError::InvalidElf)?;
+                let name = name.split(|c| *c == 0).next().unwrap();
+                match lookup(name) {
+                    Some(addr) => addr,
+                    // Undefined weak symbols resolve to zero
+                    None if sym.binding() == Binding::Weak => 0,
+                    None => return Err(LoadError::UnresolvedSymbol),
+                }
+            },
+            SHN_ABS => sym.value as usize,
+            SHN_COMMON => addr_of(&regions, &commons[&idx]),
+            shndx => match sections.get(shndx as usize) {
+                Some(Some(placement)) => addr_of(&regions, placement) + sym.value as usize,
+                // Symbols in non-allocated sections, e.g. debug info
+                _ => 0,
+            },
+        };
+        values.push(value);
+    }
+
+    // Pass 2: apply relocations, now that every symbol has a value
+    for sec in parsed.section_header_iter() {
+        if sec.sh_type() != SectionType::SHT_RELA {
+            continue;
+        }
+
+        // Relocations against non-allocated sections (e.g. debug info) are not needed at runtime
+        let target = match sections.get(sec.info() as usize) {
+            Some(Some(placement)) => *placement,
+            _ => continue,
+        };
+        let section_vaddr = addr_of(&regions, &target);
+
+        let content = section_content(buf, &sec)?;
+        let relas = unsafe { core::slice::from_raw_parts(content.as_ptr() as *const Elf64RELA, content.len() / core::mem::size_of::<Elf64RELA>()) };
+
+        let symbol = |ent: &Elf64RELA| values.get(ent.sym_index()).copied().ok_or(LoadError::InvalidElf);
+
+        // PC-relative offsets computed by HI20 relocations, by the address of their auipc,
+        // referenced by the matching LO12 relocations
+        let mut hi20 = BTreeMap::new();
+        for ent in relas {
+            if ent.reloc_type() == R_RISCV_PCREL_HI20 {
+                let pc = section_vaddr + ent.offset;
+                hi20.insert(pc, symbol(ent)?.wrapping_add(ent.addend).wrapping_sub(pc));
+            }
+        }
+
+        let region = &mut regions[target.region].content;
+        for ent in relas {
+            let s = symbol(ent)?;
+            let p = section_vaddr + ent.offset;
+            let field = region.get_mut(target.offset + ent.offset ..).ok_or(LoadError::InvalidElf)?;
+            apply_reloc(field, ent.reloc_type(), s, ent.addend, p, &hi20)?;
+        }
+    }
+
+    Ok(regions)
+}
+
+/**
+ * Apply a single relocation of type `ty` to `field`, located at address `p`
+ */
+fn apply_reloc(field: &mut [u8], ty: usize, s: usize, a: usize, p: usize, hi20: &BTreeMap<usize, usize>) -> Result<(), LoadError> {
+    let abs = s.wrapping_add(a);
+    let rel = abs.wrapping_sub(p);
+
+    // PC-relative immediates are signed, and must reach the target
+    let check = |bits: u32, value: usize| if fits_signed(value as i64, bits) {
+        Ok(())
+    } else {
+        Err(LoadError::RelocOverflow { ty })
+    };
+    // A high part is rounded up when the low 12 bits are negative
+    let check_hi20 = |value: usize| check(32, value.wrapping_add(0x800));
+
+    let mut rmw = |width: usize, f: &dyn Fn(usize) -> usize| -> Result<(), LoadError> {
+        let bytes = field.get_mut(.. width).ok_or(LoadError::InvalidElf)?;
+        let mut raw = [0u8; 8];
+        raw[.. width].copy_from_slice(bytes);
+        let value = f(usize::from_le_bytes(raw));
+        bytes.copy_from_slice(&value.to_le_bytes()[.. width]);
+        Ok(())
+    };
+
+    match ty {
+        // Relaxation is never performed, so alignment padding is kept as is
+        R_RISCV_NONE | R_RISCV_ALIGN | R_RISCV_RELAX => Ok(()),
+        R_RISCV_32 | R_RISCV_SET32 => rmw(4, &|_| abs),
+        R_RISCV_64 => rmw(8, &|_| abs),
+        R_RISCV_SET16 => rmw(2, &|_| abs),
+        R_RISCV_SET8 => rmw(1, &|_| abs),
+        R_RISCV_SET6 => rmw(1, &|v| (v & !0x3f) | (abs & 0x3f)),
+        R_RISCV_32_PCREL => rmw(4, &|_| rel),
+        R_RISCV_ADD8 => rmw(1, &|v| v.wrapping_add(abs)),
+        R_RISCV_ADD16 => rmw(2, &|v| v.wrapping_add(abs)),
+        R_RISCV_ADD32 => rmw(4, &|v| v.wrapping_add(abs)),
+        R_RISCV_ADD64 => rmw(8, &|v| v.wrapping_add(abs)),
+        R_RISCV_SUB6 => rmw(1, &|v| (v & !0x3f) | (v.wrapping_sub(abs) & 0x3f)),
+        R_RISCV_SUB8 => rmw(1, &|v| v.wrapping_sub(abs)),
+        R_RISCV_SUB16 => rmw(2, &|v| v.wrapping_sub(abs)),
+        R_RISCV_SUB32 => rmw(4, &|v| v.wrapping_sub(abs)),
+        R_RISCV_SUB64 => rmw(8, &|v| v.wrapping_sub(abs)),
+        R_RISCV_BRANCH => check(13, rel).and_then(|_| rmw(4, &|v| encode_b(v, rel))),
+        R_RISCV_JAL => check(21, rel).and_then(|_| rmw(4, &|v| encode_j(v, rel))),
+        R_RISCV_RVC_BRANCH => check(9, rel).and_then(|_| rmw(2, &|v| encode_cb(v, rel))),
+        R_RISCV_RVC_JUMP => check(12, rel).and_then(|_| rmw(2, &|v| encode_cj(v, rel))),
+        R_RISCV_CALL | R_RISCV_CALL_PLT => check_hi20(rel).and_then(|_| rmw(8, &|v| {
+            let auipc = encode_u(v & 0xffffffff, rel);
+            let jalr = encode_i(v >> 32, rel);
+            auipc | (jalr << 32)
+        })),
+        R_RISCV_PCREL_HI20 => check_hi20(rel).and_then(|_| rmw(4, &|v| encode_u(v, rel))),
+        R_RISCV_PCREL_LO12_I | R_RISCV_PCREL_LO12_S => {
+            // The symbol points at the auipc holding the high part
+            let offset = *hi20.get(&abs).ok_or(LoadError::InvalidElf)?;
+            if ty == R_RISCV_PCREL_LO12_I {
+                rmw(4, &|v| encode_i(v, offset))
+            } else {
+                rmw(4, &|v| encode_s(v, offset))
+            }
+        },
+        R_RISCV_HI20 => check_hi20(abs).and_then(|_| rmw(4, &|v| encode_u(v, abs))),
+        R_RISCV_LO12_I => rmw(4, &|v| encode_i(v, abs)),
+        R_RISCV_LO12_S => rmw(4, &|v| encode_s(v, abs)),
+        ty => Err(LoadError::UnsupportedReloc { ty }),
+    }
+}
+
+// Instruction immediate encoders. `insn` holds the instruction in its low bits, and `value` is the
+// full immediate; bits not representable by the format are dropped.
+
+/// U-type, high 20 bits, rounded so that a sign-extended low 12 bits complements it
+fn encode_u(insn: usize, value: usize) -> usize {
+    (insn & 0xfff) | (value.wrapping_add(0x800) & 0xfffff000)
+}
+
+/// I-type, low 12 bits
+fn encode_i(insn: usize, value: usize) -> usize {
+    (insn & 0xfffff) | ((value & 0xfff) << 20)
+}
+
+/// S-type, low 12 bits
+fn encode_s(insn: usize, value: usize) -> usize {
+    (insn & 0x1fff07f) | ((value & 0x1f) << 7) | (((value >> 5) & 0x7f) << 25)
+}
+
+/// B-type, 13-bit even offset
+fn encode_b(insn: usize, value: usize) -> usize {
+    (insn & 0x1fff07f)
+        | (((value >> 12) & 0x1) << 31)
+        | (((value >> 5) & 0x3f) << 25)
+        | (((value >> 1) & 0xf) << 8)
+        | (((value >> 11) & 0x1) << 7)
+}
+
+/// J-type, 21-bit even offset
+fn encode_j(insn: usize, value: usize) -> usize {
+    (insn & 0xfff)
+        | (((value >> 20) & 0x1) << 31)
+        | (((value >> 1) & 0x3ff) << 21)
+        | (((value >> 11) & 0x1) << 20)
+        | (((value >> 12) & 0xff) << 12)
+}
+
+/// CB-type (c.beqz, c.bnez), 9-bit even offset
+fn encode_cb(insn: usize, value: usize) -> usize {
+    (insn & 0xe383)
+        | (((value >> 8) & 0x1) << 12)
+        | (((value >> 3) & 0x3) << 10)
+        | (((value >> 6) & 0x3) << 5)
+        | (((value >> 1) & 0x3) << 3)
+        | (((value >> 5) & 0x1) << 2)
+}
+
+/// CJ-type (c.j, c.jal), 12-bit even offset
+fn encode_cj(insn: usize, value: usize) -> usize {
+    (insn & 0xe003)
+        | (((value >> 11) & 0x1) << 12)
+        | (((value >> 4) & 0x1) << 11)
+        | (((value >> 8) & 0x3) << 9)
+        | (((value >> 10) & 0x1) << 8)
+        | (((value >> 6) & 0x1) << 7)
+        | (((value >> 7) & 0x1) << 6)
+        | (((value >> 1) & 0x7) << 3)
+        | (((value >> 5) & 0x1) << 2)
+}
+
+#[cfg(test)]
+mod tests;
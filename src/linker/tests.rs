@@ -0,0 +1,108 @@
+use super::*;
+use crate::testing::{rela_table, symtab, ElfBuilder, MockMmu, ET_REL, RW, RX, SHF_ALLOC, SHF_EXECINSTR, SHF_WRITE, SHT_NOBITS, SHT_PROGBITS, SHT_RELA, SHT_STRTAB, SHT_SYMTAB, STB_GLOBAL, STB_LOCAL, STB_WEAK};
+use crate::loader::Loader;
+
+#[test]
+fn encoders_place_immediates() {
+    // jal x0, 8
+    assert_eq!(encode_j(0x6f, 8), 0x0080006f);
+    // beq x0, x0, 16 and beq x0, x0, -4
+    assert_eq!(encode_b(0x63, 16), 0x00000863);
+    assert_eq!(encode_b(0x63, -4isize as usize), 0xfe000ee3);
+    // c.j 2
+    assert_eq!(encode_cj(0xa001, 2), 0xa009);
+    // auipc a0, 0x1 with a low part of -1 rounds up
+    assert_eq!(encode_u(0x517, 0x1fff), 0x2517);
+    // addi a0, a0, -1 and sd a0, -8(sp)
+    assert_eq!(encode_i(0x50513, 0xfff), 0xfff50513);
+    assert_eq!(encode_s(0x00a13023, -8isize as usize), 0xfea13c23);
+}
+
+const BASE: usize = 0x1000_0000;
+
+/// A relocatable object computing the address of `data_sym` in `.text`, with a pointer to
+/// `ext + 4` in `.data`, followed by `.bss`
+fn module(ext_bind: u8) -> alloc::vec::Vec<u8> {
+    let mut builder = ElfBuilder::new(ET_REL);
+    let mut text = alloc::vec::Vec::new();
+    // auipc a0, 0; addi a0, a0, 0
+    text.extend_from_slice(&0x00000517u32.to_le_bytes());
+    text.extend_from_slice(&0x00050513u32.to_le_bytes());
+    let text_idx = builder.raw_section(b".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, &text);
+    let data_idx = builder.raw_section(b".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, &[0; 0x20]);
+    builder.raw_section(b".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, &[0; 0x10]);
+
+    let (syms, strs) = symtab(&[
+        (b".Lpcrel_hi0", 0, STB_LOCAL << 4, text_idx as u16),
+        (b"data_sym", 0x10, STB_GLOBAL << 4, data_idx as u16),
+        (b"ext", 0, ext_bind << 4, SHN_UNDEF),
+    ]);
+    let strtab_idx = builder.raw_section(b".strtab", SHT_STRTAB, 0, &strs);
+    let symtab_idx = builder.raw_section(b".symtab", SHT_SYMTAB, 0, &syms);
+    builder.sections[symtab_idx - 1].link = strtab_idx as u32;
+
+    let rela_text = rela_table(&[(0, R_RISCV_PCREL_HI20, 2, 0), (4, R_RISCV_PCREL_LO12_I, 1, 0)]);
+    builder.raw_section(b".rela.text", SHT_RELA, 0, &rela_text);
+    builder.sections.last_mut().unwrap().info = text_idx as u32;
+    let rela_data = rela_table(&[(0, R_RISCV_64, 3, 4)]);
+    builder.raw_section(b".rela.data", SHT_RELA, 0, &rela_data);
+    builder.sections.last_mut().unwrap().info = data_idx as u32;
+    builder.build()
+}
+
+#[test]
+fn load_relocatable_links_across_sections() {
+    let mut mmu = MockMmu::new();
+    let lookup = |name: &[u8]| (name == b"ext").then_some(0x5000);
+    let loader = Loader::load_relocatable(&module(STB_GLOBAL), &mut mmu, BASE, lookup).unwrap();
+
+    assert_eq!(loader.entry, 0);
+    assert_eq!(mmu.perm(BASE), Some(RX));
+    assert_eq!(mmu.perm(BASE + 0x1000), Some(RW));
+    // data_sym is 0x1010 bytes after the auipc
+    assert_eq!(mmu.read(BASE, 8), [0x17, 0x15, 0, 0, 0x13, 0x05, 0x05, 0x01]);
+    assert_eq!(mmu.word(BASE + 0x1000), 0x5004);
+    // .bss follows .data
+    assert_eq!(loader.regions.last().unwrap().vaddr, BASE + 0x1000 .. BASE + 0x1030);
+}
+
+#[test]
+fn load_relocatable_requires_strong_symbols() {
+    let mut mmu = MockMmu::new();
+    let result = Loader::load_relocatable(&module(STB_GLOBAL), &mut mmu, BASE, |_: &[u8]| None);
+    assert_eq!(result.err(), Some(LoadError::UnresolvedSymbol));
+
+    let mut mmu = MockMmu::new();
+    Loader::load_relocatable(&module(STB_WEAK), &mut mmu, BASE, |_: &[u8]| None).unwrap();
+    assert_eq!(mmu.word(BASE + 0x1000), 4);
+}
+
+#[test]
+fn pc_relative_relocations_check_range() {
+    let hi20 = BTreeMap::new();
+    let mut field = [0x6f, 0, 0, 0];
+    assert_eq!(apply_reloc(&mut field, R_RISCV_JAL, 0x10_0000, 0, 0x1000, &hi20), Ok(()));
+    assert_eq!(apply_reloc(&mut field, R_RISCV_JAL, 0x10_1000, 0, 0x1000, &hi20), Err(LoadError::RelocOverflow { ty: R_RISCV_JAL }));
+
+    let mut field = [0x63, 0, 0, 0];
+    assert_eq!(apply_reloc(&mut field, R_RISCV_BRANCH, 0, 0, 0x1000, &hi20), Ok(()));
+    assert_eq!(u32::from_le_bytes(field), encode_b(0x63, -0x1000isize as usize) as u32);
+    assert!(apply_reloc(&mut field, R_RISCV_BRANCH, 0, 0, 0x1002, &hi20).is_err());
+
+    // The high part of 0x7fff_f800 would be rounded past 2 GiB
+    let mut field = [0x17, 0, 0, 0];
+    assert_eq!(apply_reloc(&mut field, R_RISCV_PCREL_HI20, 0x7fff_f7ff, 0, 0, &hi20), Ok(()));
+    assert_eq!(apply_reloc(&mut field, R_RISCV_PCREL_HI20, 0x7fff_f800, 0, 0, &hi20), Err(LoadError::RelocOverflow { ty: R_RISCV_PCREL_HI20 }));
+}
+
+#[test]
+fn load_relocatable_rejects_overflowing_section() {
+    let mut builder = ElfBuilder::new(ET_REL);
+    let (syms, strs) = symtab(&[]);
+    let strtab_idx = builder.raw_section(b".strtab", SHT_STRTAB, 0, &strs);
+    let symtab_idx = builder.raw_section(b".symtab", SHT_SYMTAB, 0, &syms);
+    builder.sections[symtab_idx - 1].link = strtab_idx as u32;
+    builder.sections[symtab_idx - 1].size = usize::MAX;
+    let result = Loader::load_relocatable(&builder.build(), &mut MockMmu::new(), BASE, |_: &[u8]| None);
+    assert_eq!(result.err(), Some(LoadError::InvalidElf));
+}
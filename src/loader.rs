@@ -1,6 +1,8 @@
-use elf_rs::{ElfFile, SectionHeaderFlags, SectionType};
+use core::ops::Range;
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+use elf_rs::{ElfType, ProgramHeaderFlags, ProgramType, SectionHeaderFlags, SectionType};
 
-use crate::{elf::Dynamic, mem::{VirtAddr, PhysAddr}};
+use crate::{crc::{Crc32, Fnv64}, elf::{Binding, DF_1_PIE, Dynamic, Elf64RELA, FileMap, Header, Note, PT_GNU_RELRO, PT_TLS, RelTable, RiscvAttributes, SHN_UNDEF, TARGET, has_section_headers, riscv_abi, section_vaddr}, linker::{Fixup, link_relocatable, read_field, relocate_dynamic, unresolved_symbol}, mem::{VirtAddr, PhysAddr, VirtPageNum, PhysPageNum, PAGE_SIZE, PT_LEVELS, level_pages}};
 
 /**
  * Abstraction of an allocated page by an MMU
@@ -12,13 +14,38 @@ pub trait Page : Clone + Copy {
 /**
  * Mapping permission. MMU may need to additionally add U bits
  */
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub struct Perm {
     pub r: bool,
     pub w: bool,
     pub x: bool,
 }
 
+impl Perm {
+    /// Permission allowing every access allowed by either
+    pub fn union(self, other: Perm) -> Perm {
+        Perm {
+            r: self.r || other.r,
+            w: self.w || other.w,
+            x: self.x || other.x,
+        }
+    }
+}
+
+/// Formats as in `ls -l`, e.g. `r-x`
+impl core::fmt::Display for Perm {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let flag = |set: bool, c: char| if set { c } else { '-' };
+        write!(f, "{}{}{}", flag(self.r, 'r'), flag(self.w, 'w'), flag(self.x, 'x'))
+    }
+}
+
+const NO_ACCESS: Perm = Perm {
+    r: false,
+    w: false,
+    x: false,
+};
+
 /**
  * Abstraction of an MMU instance
  */
@@ -29,6 +56,19 @@ pub trait MMU {
     /// Allocate an page
     fn alloc(&mut self) -> Self::AllocatedPage;
 
+    /// Allocate a page whose paddr is aligned to `align` bytes, e.g. for frames handed to devices.
+    /// Defaults to ignoring the alignment.
+    fn alloc_aligned(&mut self, _align: usize) -> Self::AllocatedPage {
+        self.alloc()
+    }
+
+    /// Allocate a page to be mapped with `perm`, e.g. to draw executable pages from a
+    /// write-protected pool. Pages shared by several regions get the permission of the first one.
+    /// Defaults to `alloc`.
+    fn alloc_for(&mut self, _perm: Perm) -> Self::AllocatedPage {
+        self.alloc()
+    }
+
     /// Map an alloctaed page
     fn map(&mut self, page: Self::AllocatedPage, vpn: usize, perm: Perm) {
         self.map_existing(PhysAddr(page.inner() as *const u8 as usize).floor().0, vpn, perm)
@@ -37,6 +77,35 @@ pub trait MMU {
     /// Map an address not allocated, but pre-existing
     fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm);
 
+    /// Whether `map_at_level` maps huge pages with a single leaf entry, instead of falling back
+    /// to 4 KiB pages. Defaults to false.
+    fn supports_huge_pages(&self) -> bool {
+        false
+    }
+
+    /// Map a pre-existing region with a single leaf entry at the given page table level
+    /// (0 for 4 KiB, 1 for 2 MiB, 2 for 1 GiB), so huge pages can be used when supported.
+    /// Both `ppn` and `vpn` are aligned to the level. Defaults to mapping each 4 KiB page separately.
+    fn map_at_level(&mut self, ppn: usize, vpn: usize, perm: Perm, level: usize) {
+        for i in 0 .. level_pages(level) {
+            self.map_existing(ppn + i, vpn + i, perm);
+        }
+    }
+
+    /// Allocate `count` physically contiguous pages, in ascending paddr order. The loader maps
+    /// them with `map_range`. Defaults to `None`, so pages are allocated one by one.
+    fn alloc_contiguous(&mut self, _count: usize) -> Option<Vec<Self::AllocatedPage>> {
+        None
+    }
+
+    /// Map `count` contiguous pages from `ppn` onto `vpn`, e.g. frames from `alloc_contiguous`.
+    /// Defaults to mapping each page separately.
+    fn map_range(&mut self, ppn: usize, vpn: usize, count: usize, perm: Perm) {
+        for i in 0 .. count {
+            self.map_existing(ppn + i, vpn + i, perm);
+        }
+    }
+
     /// Translate an vaddr into paddr
     fn translate(&self, vaddr: usize) -> Option<usize>;
 }
@@ -44,17 +113,17 @@ pub trait MMU {
 /**
  * Configuration of VDSO linking
  */
-
 #[derive(PartialEq, Eq)]
 pub struct VDSOConfig<F> {
-    /// The start of VDSO range in physical address
+    /// The start of VDSO range in physical address, page-aligned
     pub start: usize,
 
     /// The end of VDSO range in physical address
     pub end: usize,
 
-    /// The start of VDSO in process address space
-    pub target: usize,
+    /// The start of VDSO in process address space, page-aligned. If `None`, the loader places the
+    /// VDSO in the first free pages above the image, and reports the chosen address in `Loader::vdso_base`
+    pub target: Option<usize>,
 
     /// The symbol lookup table
     pub lookup: F,
@@ -64,126 +133,2154 @@ pub struct VDSOConfig<F> {
  * Configuration of stack mapping
  */
 pub struct StackConfig {
-    /// The start of stack in process address space
-    start: usize,
+    /// The start of stack in process address space, or `None` to place it automatically
+    start: Option<usize>,
+
+    /// The size of stack in bytes
+    size: usize,
 
-    /// The end of stack in process address space
-    end: usize
+    /// Range `(low, high)` to pick a random stack top in, if randomized
+    top_window: Option<(usize, usize)>,
 }
 
-pub struct Loader {
-    pub entry: usize,
+impl StackConfig {
+    /// Stack mapped at `start .. end` in process address space
+    pub fn fixed(start: usize, end: usize) -> Self {
+        Self { start: Some(start), size: end - start, top_window: None }
+    }
+
+    /// Stack of `size` bytes, placed in the first free pages above the image and the VDSO.
+    /// The chosen range is reported in `Loader::stack`.
+    pub fn above_image(size: usize) -> Self {
+        Self { start: None, size, top_window: None }
+    }
+
+    /// Stack of `size` bytes, whose top is picked 16-byte aligned within `low ..= high` using
+    /// `LoadOptions::entropy`, and above the image. Without entropy, the highest top is used.
+    /// The chosen top is reported in `Loader::stack_top`.
+    pub fn randomized(size: usize, low: usize, high: usize) -> Self {
+        Self { start: None, size, top_window: Some((low, high)) }
+    }
+
+    /// Pick the start of the stack if it doesn't need to be placed by the loader, given the
+    /// lowest address the stack may occupy
+    fn start_above(&self, floor: usize, entropy: Option<&mut dyn FnMut() -> u64>) -> Result<Option<usize>, LoadError> {
+        let Some((low, high)) = self.top_window else { return Ok(self.start) };
+        let lowest = low.max(floor.checked_add(self.size).ok_or(LoadError::NoSpace)?).next_multiple_of(16);
+        let highest = high & !15;
+        if lowest > highest {
+            return Err(LoadError::NoSpace);
+        }
+
+        let slots = ((highest - lowest) / 16 + 1) as u64;
+        let top = match entropy {
+            Some(entropy) => lowest + (entropy() % slots) as usize * 16,
+            None => highest,
+        };
+        Ok(Some(top - self.size))
+    }
 }
 
-impl Loader {
+/// Lookup of a symbol by name, returning its process vaddr
+pub type SymbolLookup<'a> = dyn FnMut(&[u8]) -> Option<usize> + 'a;
+
+/**
+ * Options controlling how an ELF is loaded
+ */
+pub struct LoadOptions<'a> {
+    /// Defer applying relocations until their page is first accessed. Pages holding relocations
+    /// are mapped inaccessible, and the page fault handler calls `Loader::relocate_page`.
+    /// Ignored for objects requesting BIND_NOW, which are always relocated eagerly.
+    pub lazy_relocation: bool,
+
+    /// Fall back to a minimal parser of the ELF header when elf_rs rejects the file, for
+    /// `Loader::load_segments`. See `Header::parse_lenient` for the tolerated deviations.
+    pub lenient: bool,
+
+    /// Record a CRC32 of each non-writable region while copying it, so that
+    /// `Loader::verify_integrity` can later detect corruption of code and read-only data.
+    /// Relocations later applied inside such regions (text relocations) are not accounted for.
+    pub segment_crc: bool,
+
+    /// Vaddr ranges `(start, end)` kept free from automatically placed regions, e.g. the VDSO.
+    /// Regions with an explicit address, such as segments, may still be mapped inside them.
+    pub reserved_ranges: &'a [(usize, usize)],
+
+    /// Leave `GOT[0]` as found in the file, instead of pointing it at the relocated `_DYNAMIC`
+    /// as expected by dynamic linkers bootstrapping themselves.
+    pub preserve_got0: bool,
+
+    /// Names of sections backing device-facing buffers. Pages overlapping them are allocated
+    /// through `MMU::alloc_aligned` with `dma_align`.
+    pub dma_sections: &'a [&'a [u8]],
+
+    /// Paddr alignment in bytes of frames backing `dma_sections`
+    pub dma_align: usize,
+
+    /// Before patching, check that each slot holds what the static linker leaves for its
+    /// relocation type: zero or the addend. Meant for debugging, this catches relocating the
+    /// wrong slot, e.g. after an incorrect bias.
+    pub check_prevalues: bool,
+
+    /// Make the PT_GNU_RELRO range read-only after relocation. Pages are protected as a whole,
+    /// so a RELRO range ending mid-page also protects the data following it on that page; linkers
+    /// normally pad RELRO up to a page boundary to avoid that.
+    pub protect_relro: bool,
+
+    /// Number of unmapped pages kept between the image and the regions placed automatically
+    /// above it (VDSO and stack), and between those regions, so that linear overruns fault.
+    /// Regions at an explicit address are not moved.
+    pub min_gap_pages: usize,
+
+    /// Source of randomness for placement decisions, e.g. sliding an automatically placed VDSO
+    /// by up to `VDSO_SLIDE_PAGES`. Without one, loading is deterministic.
+    pub entropy: Option<&'a mut dyn FnMut() -> u64>,
+
+    /// Fail with `LoadError::UnsupportedReloc` on dynamic relocation types the loader doesn't
+    /// implement, instead of skipping them. Enabled by default.
+    pub strict_relocs: bool,
+
+    /// Fail with `LoadError::HugePageUnsupported` unless the VDSO can be mapped entirely with huge
+    /// pages, for callers relying on it, e.g. a device window that must be a single 2 MiB page
+    pub require_huge: bool,
+
+    /// Map the pages of the image, then those of the stack, from the highest vaddr down, for cores
+    /// whose TLB prefetching prefers it. By default, each is mapped in ascending vaddr order.
+    pub map_descending: bool,
+
+    /// Record the weak undefined symbols no object provides in `Loader::weak_undefined`. They
+    /// resolve to zero either way.
+    pub record_weak_undef: bool,
+
+    /// Process vaddrs of undefined symbols, e.g. precomputed for a VDSO at a fixed address when
+    /// prelinking. Symbols listed here are not looked up in the VDSO.
+    pub symbol_overrides: &'a [(&'a [u8], usize)],
+
+    /// Process vaddrs of undefined symbols found neither in `symbol_overrides` nor in the VDSO,
+    /// e.g. symbols exported by the kernel at fixed addresses
+    pub lookup: Option<&'a mut SymbolLookup<'a>>,
+
+    /// Fail with `LoadError::NullPage` if anything would be mapped in page 0, so that null
+    /// dereferences fault. Segments from 0x1000 up, one page above null, are fine.
+    pub null_guard: bool,
+
+    /// Vaddr range every page mapped for the process must lie in: segments, VDSO and stack,
+    /// failing with `LoadError::OutsideUserSpace` otherwise, e.g. the lower half when the kernel
+    /// occupies the upper one. The heap, growing from `Loader::data_end`, is left to the caller.
+    pub user_space: Range<usize>,
+
+    /// Keep the page following each PT_LOAD segment unmapped, so that overrunning any segment
+    /// faults. Segments keep their addresses, so this fails with `LoadError::GuardPageMapped`
+    /// when the next segment starts on that page. Consecutive segments mapped as a single region
+    /// only get a guard page after the last one. `load` also takes the segments from the program
+    /// headers, even though it maps sections.
+    pub per_segment_guard: bool,
+
+    /// TLS module id of the object, recorded in `Loader::tls_module` if it has a TLS segment, and
+    /// resolving its TLS relocations. Defaults to 1, the main program.
+    pub tls_module: usize,
+
+    /// After eager relocation, fail with `LoadError::IncompleteGot` if a slot of `.got` or
+    /// `.got.plt` is still zero, e.g. because its relocation was skipped. Slots relocated against
+    /// undefined weak symbols, slots without dynamic relocation whose value the static linker
+    /// resolved, and the `.got.plt` header reserved for the dynamic linker, may be zero. Needs
+    /// section headers, and is ignored when relocations are deferred.
+    pub assert_got_complete: bool,
+
+    /// Fail with `LoadError::TooManyRelocations` before mapping anything if the relocation table
+    /// declares more entries, bounding the work done for a hostile ELF
+    pub max_relocations: Option<usize>,
+}
+
+impl LoadOptions<'_> {
+    /// Options for another load of the same process, borrowing the entropy source
+    fn reborrow(&mut self) -> LoadOptions<'_> {
+        LoadOptions {
+            lazy_relocation: self.lazy_relocation,
+            lenient: self.lenient,
+            segment_crc: self.segment_crc,
+            reserved_ranges: self.reserved_ranges,
+            preserve_got0: self.preserve_got0,
+            dma_sections: self.dma_sections,
+            dma_align: self.dma_align,
+            check_prevalues: self.check_prevalues,
+            protect_relro: self.protect_relro,
+            min_gap_pages: self.min_gap_pages,
+            entropy: match &mut self.entropy {
+                Some(entropy) => Some(&mut **entropy as &mut dyn FnMut() -> u64),
+                None => None,
+            },
+            strict_relocs: self.strict_relocs,
+            require_huge: self.require_huge,
+            map_descending: self.map_descending,
+            record_weak_undef: self.record_weak_undef,
+            symbol_overrides: self.symbol_overrides,
+            lookup: match &mut self.lookup {
+                Some(lookup) => Some(&mut **lookup as &mut SymbolLookup),
+                None => None,
+            },
+            null_guard: self.null_guard,
+            user_space: self.user_space.clone(),
+            per_segment_guard: self.per_segment_guard,
+            tls_module: self.tls_module,
+            assert_got_complete: self.assert_got_complete,
+            max_relocations: self.max_relocations,
+        }
+    }
+}
+
+impl Default for LoadOptions<'_> {
+    fn default() -> Self {
+        Self {
+            lazy_relocation: false,
+            lenient: false,
+            segment_crc: false,
+            reserved_ranges: &[],
+            preserve_got0: false,
+            dma_sections: &[],
+            dma_align: 0,
+            check_prevalues: false,
+            protect_relro: false,
+            min_gap_pages: 0,
+            entropy: None,
+            strict_relocs: true,
+            require_huge: false,
+            map_descending: false,
+            record_weak_undef: false,
+            symbol_overrides: &[],
+            lookup: None,
+            null_guard: false,
+            user_space: 0 .. usize::MAX,
+            per_segment_guard: false,
+            tls_module: 1,
+            assert_got_complete: false,
+            max_relocations: None,
+        }
+    }
+}
+
+/// Number of `.got.plt` slots reserved for the dynamic linker on RISC-V: the lazy resolver, and
+/// the object's link map
+const GOT_PLT_RESERVED: usize = 2;
+
+/// Maximum number of pages skipped above the image when randomizing the VDSO placement
+pub const VDSO_SLIDE_PAGES: usize = 256;
+
+/**
+ * Errors reported while loading an ELF
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+    /// The buffer is not a well-formed ELF64 file for `elf::TARGET`
+    InvalidElf,
+
+    /// A segment has more file bytes than memory bytes (`p_filesz > p_memsz`)
+    BadSegmentSizes,
+
+    /// The RELA table isn't a whole number of `DT_RELAENT`-sized entries within the file, as
+    /// declared by `DT_RELASZ`
+    BadRelocTable,
+
+    /// A segment's vaddr and file offset are not congruent modulo its alignment. A `p_align` of 0
+    /// or 1 imposes no constraint, and such segments are placed at page granularity.
+    BadSegmentAlignment,
+
+    /// The bias is not a multiple of a segment's alignment, see `Loader::required_base_align`
+    MisalignedBias,
+
+    /// An undefined symbol was not found by the lookup
+    UnresolvedSymbol,
+
+    /// The relocation type is not implemented
+    UnsupportedReloc { ty: usize },
+
+    /// The image doesn't fit in the requested address range
+    NoSpace,
+
+    /// A relocation patches memory outside of the loaded image
+    RelocOutOfRange,
+
+    /// The relocated value of type `ty` doesn't fit in its field
+    RelocOverflow { ty: usize },
+
+    /// The VDSO range is empty, or its start or target is not page-aligned
+    BadVDSORange,
+
+    /// Two images loaded into the same address space overlap
+    Overlap,
+
+    /// A relocated slot held an unexpected value before patching, see `LoadOptions::check_prevalues`
+    UnexpectedPrevalue { vaddr: usize },
+
+    /// Huge pages were required, but the MMU doesn't support them or the VDSO range isn't made of
+    /// whole aligned huge pages, see `LoadOptions::require_huge`
+    HugePageUnsupported,
+
+    /// A region would be mapped in page 0, see `LoadOptions::null_guard`
+    NullPage,
+
+    /// A page would be mapped outside of `LoadOptions::user_space`
+    OutsideUserSpace,
+
+    /// The page following a region of the image would be mapped, see
+    /// `LoadOptions::per_segment_guard`
+    GuardPageMapped,
+
+    /// A GOT slot is still zero after relocation, see `LoadOptions::assert_got_complete`.
+    /// `offset` is its link-time vaddr.
+    IncompleteGot { offset: usize },
+
+    /// The relocation table has more entries than `LoadOptions::max_relocations`
+    TooManyRelocations,
+}
+
+/**
+ * A problem found with a dynamic relocation, by `Loader::check_relocations`
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum RelocIssue {
+    /// The referenced symbol is undefined, and unknown to the resolver
+    Unresolved { offset: usize, name: Vec<u8> },
+
+    /// The relocation patches memory outside of every PT_LOAD segment
+    OutOfRange { offset: usize },
+
+    /// The relocation type is not implemented
+    Unsupported { offset: usize, ty: usize },
+
+    /// The relocated value doesn't fit in the field of its type
+    Overflow { offset: usize, ty: usize },
+}
+
+/**
+ * A non-weak undefined symbol the VDSO doesn't export, by `Loader::check_vdso`
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MissingSymbol {
+    pub name: Vec<u8>,
+
+    /// Link-time vaddrs of the slots relocated against it
+    pub offsets: Vec<usize>,
+}
+
+/**
+ * Result of applying a single dynamic relocation, by `Loader::apply_reloc_entry`
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RelocOutcome {
+    /// The relocation has nothing to write, e.g. R_RISCV_NONE
+    Skipped,
+
+    /// `value` was written at `vaddr`
+    Applied { vaddr: usize, value: usize },
+}
+
+/**
+ * A region of the ELF image mapped into the process address space
+ */
+#[derive(Clone, Debug)]
+pub struct Region {
+    /// The mapped range in process address space, bias applied
+    pub vaddr: Range<usize>,
+
+    /// Mapping permission
+    pub perm: Perm,
+
+    /// CRC32 of the region's content as loaded, if recorded
+    pub crc: Option<u32>,
+
+    /// Offset in the ELF file of the content at the start of the region
+    pub file_offset: usize,
+
+    /// Number of bytes copied from the file, the rest being zero-filled. Zero for regions not
+    /// backed by the file, such as .bss or linked relocatable objects.
+    pub file_size: usize,
+}
+
+impl Region {
+    /// Number of zero-filled bytes following the file content, e.g. .bss at the end of a segment.
+    /// Only meaningful for regions loaded from an ELF file, see `file_size`.
+    pub fn zero_size(&self) -> usize {
+        self.vaddr.len() - self.file_size
+    }
+
     /**
-     * Load an elf providing an MMU and various configurations.
+     * Vpns of the region holding no file content, e.g. for a pager to back them with a shared zero
+     * frame until written. The page holding the end of the content is file-backed. The last page
+     * may also hold the start of a following region. Same caveat as `zero_size`.
      */
-    pub fn load<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], mmu: &mut M, ldso: Option<VDSOConfig<F>>, stack: StackConfig) -> Loader {
-        let parsed = elf_rs::Elf64::from_bytes(buf).unwrap();
+    pub fn zero_pages(&self) -> Range<usize> {
+        let start = VirtAddr(self.vaddr.start + self.file_size).ceil().number();
+        let end = VirtAddr(self.vaddr.end).ceil().number();
+        start.min(end) .. end
+    }
+}
+
+/**
+ * A run of pages mapped contiguously, reported by `Loader::plan`
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct LayoutEntry {
+    pub vaddr: Range<usize>,
+    pub ppn: Range<usize>,
+    pub perm: Perm,
+}
 
-        let mut dynamic = None;
+/**
+ * What backs a mapping reported by `Loader::maps`
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MapKind {
+    /// Content of the ELF file from `offset`, the offset of the first page of the mapping
+    File { offset: usize },
 
-        // Allocate memories
-        for sec_hdr in parsed.section_header_iter() {
-            if sec_hdr.section_name().starts_with(b".dynamic") {
-                dynamic = Some(Dynamic::parse(buf, sec_hdr.offset() as usize .. (sec_hdr.offset()  + sec_hdr.size()) as usize));
-            }
+    /// Zero-filled memory, e.g. .bss past the file content of a segment
+    Anon,
 
-            if !sec_hdr.flags().contains(SectionHeaderFlags::SHF_ALLOC) {
-                continue;
-            }
+    Stack,
+    Vdso,
+}
 
-            let addr = sec_hdr.addr() as usize;
-            let size = sec_hdr.size() as usize;
-            assert!(size > 0);
+/**
+ * A mapping of the process, as listed by `/proc/pid/maps`, reported by `Loader::maps`
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MapEntry {
+    pub vaddr: Range<usize>,
+    pub perm: Perm,
+    pub kind: MapKind,
+}
 
-            let src = if sec_hdr.sh_type() != SectionType::SHT_NOBITS {
-                let offset = sec_hdr.offset() as usize;
-                let content = &buf[offset..(offset + size)];
-                Some(content)
-            } else {
-                None
-            };
+/**
+ * A dynamically linked program loaded together with its interpreter
+ */
+pub struct ProcessImage {
+    pub main: Loader,
+    pub interpreter: Loader,
+}
 
-            let virt_start: usize = VirtAddr(addr).floor().number();
-            let virt_end: usize = VirtAddr(addr + size).ceil().number();
-            let perm = Perm {
-                r: true,
-                w: sec_hdr.flags().contains(SectionHeaderFlags::SHF_WRITE),
-                x: sec_hdr.flags().contains(SectionHeaderFlags::SHF_EXECINSTR),
-            };
+impl ProcessImage {
+    /// Where the process starts: the interpreter, which finishes linking the main image
+    pub fn entry(&self) -> usize {
+        self.interpreter.entry
+    }
 
-            // Alloc pages
-            for vpn in virt_start .. virt_end {
-                let page = mmu.alloc();
-                // TODO: copy pages
-                mmu.map(page, vpn, perm);
-            }
+    /// TLS template of the object assigned TLS module id `module`
+    pub fn tls_block(&self, module: usize) -> Option<&TlsTemplate> {
+        [&self.main, &self.interpreter].into_iter()
+            .find(|object| object.tls_module == Some(module))
+            .and_then(|object| object.tls.as_ref())
+    }
+}
+
+/**
+ * A loaded and relocated process kept as a template, whose frames new address spaces share
+ * copy-on-write, e.g. for fast fork. Created by `Loader::freeze`.
+ */
+pub struct FrozenImage {
+    /// Layout shared by every instance: entry, stack, TLS template and so on
+    pub loader: Loader,
+
+    /// Template frame and permission of every page, by vpn
+    frames: BTreeMap<usize, (usize, Perm)>,
+}
+
+impl FrozenImage {
+    /**
+     * Map the template into the address space of `mmu`. Read-only pages share the template's
+     * frames, writable ones as well but mapped read-only until `copy_on_write` copies them.
+     */
+    pub fn instantiate<M: MMU>(&self, mmu: &mut M) {
+        for (vpn, (ppn, perm)) in self.frames.iter() {
+            mmu.map_existing(*ppn, *vpn, Perm { w: false, ..*perm });
         }
+    }
 
-        // Map VDSO text
-        if let Some(mut config) = ldso {
-            let text_vdso_start_ppn = PhysAddr(config.start).floor().0;
-            let text_vdso_end_ppn = PhysAddr(config.end).ceil().0;
-            let text_vdso_start_vpn = VirtAddr(config.target).floor().0;
+    /**
+     * Give the page containing `vaddr` a private copy of its template frame, mapped with its
+     * permission. Meant to be called from the page fault handler on a write to an instance.
+     * Returns false if the page isn't writable, or was already copied.
+     */
+    pub fn copy_on_write<M: MMU>(&self, mmu: &mut M, vaddr: usize) -> bool {
+        let vpn = VirtAddr(vaddr).floor();
+        let Some((ppn, perm)) = self.frames.get(&vpn.number()).copied() else { return false };
+        if !perm.w || mmu.translate(VirtAddr::from(vpn).0).map(|paddr| PhysAddr(paddr).floor().0) != Some(ppn) {
+            return false;
+        }
+
+        let page = mmu.alloc_for(perm);
+        let template = PhysAddr::from(PhysPageNum(ppn)).0 as *const u8;
+        unsafe { core::ptr::copy_nonoverlapping(template, page.inner().as_ptr() as *mut u8, PAGE_SIZE) };
+        mmu.map(page, vpn.number(), perm);
+        true
+    }
+}
+
+/**
+ * Kind of ELF file, as told by `e_type` and the dynamic array
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ElfKind {
+    /// Position-dependent executable (ET_EXEC)
+    Executable,
+
+    /// Position-independent executable: ET_DYN with a program interpreter or `DF_1_PIE`
+    Pie,
+
+    /// Other ET_DYN objects: shared libraries, dynamic linkers, and static PIEs not flagged as such
+    SharedObject,
+
+    /// Relocatable object (ET_REL), see `Loader::load_relocatable`
+    Relocatable,
+
+    Other,
+}
+
+/**
+ * Summary of an ELF from its headers and dynamic array, by `Loader::inspect`
+ */
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ElfInfo<'a> {
+    pub kind: ElfKind,
+
+    /// Link-time entry point
+    pub entry: usize,
+
+    /// Path of the program interpreter from PT_INTERP, without the terminating NUL
+    pub interpreter: Option<&'a [u8]>,
+
+    /// Names of the shared libraries depended on (`DT_NEEDED`)
+    pub needed: Vec<&'a [u8]>,
+
+    /// Bytes of address space the PT_LOAD segments span, see `Loader::image_span`. Zero without any.
+    pub footprint: usize,
+}
+
+/**
+ * Pages the PT_LOAD segments of an ELF would occupy, computed without mapping anything
+ */
+pub struct LoadPlan {
+    /// Permission of every page, merged across segments sharing it, by vpn
+    pages: BTreeMap<usize, Perm>,
+}
+
+impl LoadPlan {
+    /// Plan loading `buf` by `Loader::load_segments` with `bias`
+    pub fn new(buf: &[u8], bias: usize) -> Result<Self, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let mut pages = BTreeMap::new();
+        for ph in header.phdrs.iter().filter(|ph| ph.ty == ProgramType::LOAD) {
+            if ph.filesz > ph.memsz {
+                return Err(LoadError::BadSegmentSizes);
+            }
 
             let perm = Perm {
-                x: true,
-                r: true,
-                w: false,
+                r: ph.flags.contains(ProgramHeaderFlags::READ),
+                w: ph.flags.contains(ProgramHeaderFlags::WRITE),
+                x: ph.flags.contains(ProgramHeaderFlags::EXECUTE),
             };
-
-            for ppn in text_vdso_start_ppn .. text_vdso_end_ppn {
-                let pcount = ppn - text_vdso_start_ppn;
-                let vpn = text_vdso_start_vpn + pcount;
-                mmu.map_existing(ppn, vpn, perm);
-            }
-
-            if let Some(dynamic) = &dynamic {
-                if let Some(inner) = &dynamic.rel {
-                    match &inner {
-                        crate::elf::RelTable::RELA(tbl) => {
-                            for ent in *tbl {
-                                let (sym, name) = dynamic.resolve_sym(ent.info >> 32);
-                                if let Some(at) = (config.lookup)(name) {
-                                    // Found, fill in GOT
-                                    let target_offset = at - config.start as usize;
-                                    let target_vaddr = config.target + target_offset;
-                                    let got_vaddr = ent.offset;
-                                    let got_paddr = mmu.translate(got_vaddr.into()).unwrap();
-                                    unsafe { (got_paddr as *mut usize).write(target_vaddr) };
-                                }
-                            }
-                        },
-                        crate::elf::RelTable::REL(_) => todo!(),
-                    }
-                }
+            let vaddr = ph.vaddr.checked_add(bias).ok_or(LoadError::InvalidElf)?;
+            let end = vaddr.checked_add(ph.memsz).ok_or(LoadError::InvalidElf)?;
+            for vpn in VirtAddr(vaddr).floor().number() .. VirtAddr(end).ceil().number() {
+                pages.entry(vpn).and_modify(|existing: &mut Perm| *existing = existing.union(perm)).or_insert(perm);
             }
         }
+        Ok(Self { pages })
+    }
 
-        // Fixup GOT
+    /// Permission `vaddr` would be mapped with by the image, or `None` if it stays unmapped
+    pub fn covers(&self, vaddr: usize) -> Option<Perm> {
+        self.pages.get(&VirtAddr(vaddr).floor().number()).copied()
+    }
+}
 
-        // Allocate stack
+pub struct Loader {
+    pub entry: usize,
 
-        // TODO: extendable stack
-        let stack_end = VirtAddr(stack.end).ceil().number();
-        let stack_start = VirtAddr(stack.start).floor().number();
-        let stack_perm = Perm {
-            r: true,
-            w: true,
-            x: false,
-        };
+    /// Offset added to the link-time vaddrs of the image, zero for `Loader::restore`
+    pub bias: usize,
 
-        for stack_vpn in stack_start .. stack_end {
-            let page = mmu.alloc();
-            mmu.map(page, stack_vpn, stack_perm);
-        }
+    /// Vaddr of the program headers in the process, for AT_PHDR, if they are loaded
+    pub phdr: Option<usize>,
 
-        let entry = parsed.entry_point() as usize;
+    /// Permission of the page containing `entry`, no access if it isn't mapped. Callers may
+    /// check `x` before jumping to it.
+    pub entry_perm: Perm,
 
-        Loader {
-            entry,
-        }
+    /// Regions mapped from the ELF image, in the order they were mapped
+    pub regions: Vec<Region>,
+
+    /// Start of the VDSO in process address space, e.g. for AT_SYSINFO_EHDR
+    pub vdso_base: Option<usize>,
+
+    /// Stack range in process address space, empty for relocatable objects
+    pub stack: Range<usize>,
+
+    /// Top of the stack, where the initial stack pointer goes
+    pub stack_top: usize,
+
+    /// Initialization template of thread-local storage, if the ELF has a PT_TLS segment
+    pub tls: Option<TlsTemplate>,
+
+    /// TLS module id of this object, for `__tls_get_addr` and TLS descriptors, if it has a TLS
+    /// segment: 1 for the main program, counting up across the objects loaded along with it
+    pub tls_module: Option<usize>,
+
+    /// Build attributes from `.riscv.attributes`, if the section is present
+    pub attributes: Option<RiscvAttributes>,
+
+    /// ABI of the image encoded in its ELF header, e.g. `lp64d`
+    pub abi: Option<&'static str>,
+
+    /// End of the highest writable page of the image, e.g. for the initial brk. The end of the
+    /// image if nothing in it is writable.
+    pub data_end: usize,
+
+    /// Page-rounded vaddr range of PT_GNU_RELRO, for callers write-protecting it themselves once
+    /// initializers have run, instead of using `LoadOptions::protect_relro`
+    pub relro: Option<Range<usize>>,
+
+    /// Number of entries of the dynamic relocation tables written, applied or deferred, including
+    /// those written into the TLS template. The GOT[0] update isn't one of them. Zero for static
+    /// executables, which have nothing to relocate.
+    pub relocations: usize,
+
+    /// Whether a page had to be writable and executable at once: mapped so, or patched while
+    /// executable by a text relocation. The loader writes relocations through the physical
+    /// mapping, so the latter never maps the page W+X, but is reported as risky all the same.
+    pub had_wx_window: bool,
+
+    /// Distinct names of the weak undefined symbols resolved to zero, in relocation order, when
+    /// loaded with `LoadOptions::record_weak_undef`
+    pub weak_undefined: Vec<Vec<u8>>,
+
+    /// Dynamic relocations skipped because their type isn't supported, as `(vaddr, ty)` with the
+    /// process vaddr of the slot. Only filled when `LoadOptions::strict_relocs` is off.
+    pub skipped_relocs: Vec<(usize, usize)>,
+
+    /// Process vaddrs of the symbols exported through the dynamic symbol table
+    symbols: BTreeMap<Vec<u8>, usize>,
+
+    /// Final permission of every page mapped for the process, including the VDSO and stack, by vpn
+    mapped: BTreeMap<usize, Perm>,
+
+    /// Deferred relocations, sorted by target vaddr
+    pending: Vec<Fixup>,
+
+    /// Permission to restore for pages with deferred relocations, by vpn
+    pending_perm: BTreeMap<usize, Perm>,
+
+    /// Range of `pending` targeting each page with deferred relocations
+    reloc_index: BTreeMap<VirtPageNum, Range<usize>>,
+
+    /// Vaddrs of the relocated slots pointing into the VDSO, and the VDSO length
+    vdso_slots: Vec<usize>,
+    vdso_len: usize,
+}
+
+/**
+ * Symbol resolver over already loaded objects, searched in load order. The first object defining
+ * a symbol preempts every later one, regardless of binding, like a dynamic linker does: e.g. an
+ * interposer loaded first overrides the `malloc` of the libraries after it.
+ */
+pub fn resolve_in_order<'a>(objects: &'a [&'a Loader]) -> impl Fn(&[u8]) -> Option<usize> + 'a {
+    move |name| objects.iter().find_map(|object| object.symbol_vaddr(name))
+}
+
+/**
+ * Initialization image of a thread-local storage block
+ */
+#[derive(Clone, Debug)]
+pub struct TlsTemplate {
+    /// Content of a fresh TLS block: .tdata followed by zero-filled .tbss
+    pub image: Vec<u8>,
+
+    /// Alignment each thread's TLS block must be allocated at, from PT_TLS's `p_align`. At least 1.
+    pub align: usize,
+}
+
+impl TlsTemplate {
+    /// Size of each thread's TLS block, including .tbss
+    pub fn block_size(&self) -> usize {
+        self.image.len()
+    }
+
+    /// Initialize a thread's TLS block, which must be at least `block_size` bytes long
+    pub fn init_block(&self, block: &mut [u8]) {
+        block[.. self.image.len()].copy_from_slice(&self.image);
     }
-}
\ No newline at end of file
+}
+
+/**
+ * Memory of an ELF image mapped so far, before linking
+ */
+struct Image<P> {
+    /// Offset added to every vaddr in the ELF
+    bias: usize,
+
+    /// Permission of every mapped page, by vpn, merged across the regions sharing it
+    mapped: BTreeMap<usize, Perm>,
+
+    /// Frames filled but not mapped yet, by vpn. They are mapped once with their final
+    /// permission by `commit`, after every region is laid out.
+    frames: BTreeMap<usize, P>,
+
+    /// Frame each page was mapped onto by `commit`, by vpn
+    ppns: BTreeMap<usize, usize>,
+
+    regions: Vec<Region>,
+
+    /// Whether to record the CRC of non-writable regions
+    segment_crc: bool,
+
+    /// Vpn ranges to back with aligned frames, and their alignment
+    dma: Vec<Range<usize>>,
+    dma_align: usize,
+
+    /// Map pages from the highest vaddr down
+    map_descending: bool,
+
+    /// Vpn runs backed by frames from `MMU::alloc_contiguous`
+    contiguous: Vec<Range<usize>>,
+}
+
+/// Run of PT_LOAD segments mapped as a single region, as `(vaddr, perm, file offset, file size)`
+type SegmentRun = (Range<usize>, Perm, usize, usize);
+
+impl Loader {
+    /**
+     * Load an elf providing an MMU and various configurations.
+     * Memory is laid out according to the SHF_ALLOC sections, at their link-time address.
+     */
+    pub fn load<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], mmu: &mut M, ldso: Option<VDSOConfig<F>>, stack: StackConfig, options: LoadOptions) -> Result<Loader, LoadError> {
+        let parsed = elf_rs::Elf64::from_bytes(buf).map_err(|_| LoadError::InvalidElf)?;
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+
+        // Sections may appear in any order. Stage them by address, so that pages shared by several
+        // sections, and the order of regions, don't depend on the section header table order.
+        // SHF_TLS sections only make up the TLS template, built from PT_TLS, and .tbss doesn't
+        // even have an address of its own, so they are not mapped.
+        let mut sections: Vec<_> = parsed.section_header_iter()
+            .filter(|sec_hdr| sec_hdr.flags().contains(SectionHeaderFlags::SHF_ALLOC))
+            .filter(|sec_hdr| !sec_hdr.flags().contains(SectionHeaderFlags::SHF_TLS))
+            .collect();
+        sections.sort_by_key(|sec_hdr| sec_hdr.addr());
+
+        let mut image = Image::new(buf, 0, &options);
+        for sec_hdr in sections {
+            let addr = sec_hdr.addr() as usize;
+            let size = sec_hdr.size() as usize;
+            if size == 0 {
+                continue;
+            }
+
+            let perm = Perm {
+                r: true,
+                w: sec_hdr.flags().contains(SectionHeaderFlags::SHF_WRITE),
+                x: sec_hdr.flags().contains(SectionHeaderFlags::SHF_EXECINSTR),
+            };
+
+            // Copy file-backed sections; NOBITS ones (.bss) are only zero-filled
+            let (file_offset, file_size) = match sec_hdr.sh_type() {
+                SectionType::SHT_NOBITS => (0, 0),
+                _ => (sec_hdr.offset() as usize, size),
+            };
+            let content = file_offset.checked_add(file_size)
+                .and_then(|end| buf.get(file_offset .. end))
+                .ok_or(LoadError::InvalidElf)?;
+            let end = addr.checked_add(size).ok_or(LoadError::InvalidElf)?;
+            image.map_region_with(mmu, addr .. end, perm, file_offset, file_size, &mut |offset, dst| {
+                dst.copy_from_slice(&content[offset .. offset + dst.len()]);
+            });
+        }
+
+        Self::link(buf, &header, image, mmu, ldso, stack, options)
+    }
+
+    /**
+     * Load an elf according to its PT_LOAD segments, each placed at `p_vaddr + bias`.
+     * Segment contents are copied from the file, and the remaining memory is zero-filled.
+     * With `LoadOptions::lenient`, files rejected by elf_rs are parsed by `Header::parse_lenient`.
+     */
+    pub fn load_segments<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], mmu: &mut M, bias: usize, ldso: Option<VDSOConfig<F>>, stack: StackConfig, options: LoadOptions) -> Result<Loader, LoadError> {
+        let header = Header::parse(buf)
+            .or_else(|| options.lenient.then(|| Header::parse_lenient(buf)).flatten())
+            .ok_or(LoadError::InvalidElf)?;
+
+        let mut image = Image::new(buf, bias, &options);
+        if header.phdrs.iter().any(|ph| ph.ty == ProgramType::LOAD && ph.file_range().and_then(|range| buf.get(range)).is_none()) {
+            return Err(LoadError::InvalidElf);
+        }
+        Self::map_segments(&header, &mut image, mmu, &mut |offset, dst| {
+            dst.copy_from_slice(&buf[offset .. offset + dst.len()]);
+        })?;
+
+        Self::link(buf, &header, image, mmu, ldso, stack, options)
+    }
+
+    /**
+     * Load `buf` by segments at its link-time vaddrs together with its program interpreter
+     * `interp` (e.g. ld.so) at `interp_base`, in the same address space. Both images are
+     * relocated. The VDSO and the stack are set up for the main image only, and the process
+     * should start at `ProcessImage::entry`, the interpreter's entry point. Undefined symbols of
+     * the interpreter itself, e.g. syscall stubs, are resolved against the VDSO mapped for the
+     * main image, then `options.lookup`.
+     */
+    pub fn load_with_interpreter<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], interp: &[u8], mmu: &mut M, interp_base: usize, mut ldso: Option<VDSOConfig<F>>, stack: StackConfig, mut options: LoadOptions) -> Result<ProcessImage, LoadError> {
+        let main_ldso = ldso.as_mut().map(|config| VDSOConfig { start: config.start, end: config.end, target: config.target, lookup: &mut config.lookup });
+        let main = Self::load_segments(buf, mmu, 0, main_ldso, stack, options.reborrow())?;
+
+        let bias = Self::bias_for_base(interp, interp_base)?;
+        if LoadPlan::new(interp, bias)?.pages.keys().any(|vpn| main.mapped.contains_key(vpn)) {
+            return Err(LoadError::Overlap);
+        }
+        // An empty stack maps nothing
+        let no_stack = StackConfig { start: Some(0), size: 0, top_window: None };
+        let mut vdso = ldso.zip(main.vdso_base);
+        let mut fallback = options.lookup.take();
+        let mut lookup = |name: &[u8]| match &mut vdso {
+            Some((config, vdso_base)) => (config.lookup)(name).map(|at| *vdso_base + (at - config.start)),
+            None => None,
+        }.or_else(|| fallback.as_mut().and_then(|lookup| lookup(name)));
+        let tls_module = main.tls_module.map_or(options.tls_module, |id| id + 1);
+        let interp_options = LoadOptions { lookup: Some(&mut lookup), tls_module, ..options.reborrow() };
+        let interpreter = Self::load_segments(interp, mmu, bias, None::<VDSOConfig<F>>, no_stack, interp_options)?;
+
+        Ok(ProcessImage { main, interpreter })
+    }
+
+    /**
+     * Same as `load_segments`, with the headers parsed from `header_buf`, and segment contents
+     * pulled by `fetch` directly into the allocated pages. `fetch` fills its destination with
+     * the file content starting at the given offset. `header_buf` must hold the ELF and program
+     * headers. If the ELF has a dynamic array or TLS, the loaded segments are read back to locate
+     * them, as they usually live outside of the headers.
+     */
+    pub fn load_segments_fetched<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(header_buf: &[u8], fetch: &mut dyn FnMut(usize, &mut [u8]), mmu: &mut M, bias: usize, ldso: Option<VDSOConfig<F>>, stack: StackConfig, options: LoadOptions) -> Result<Loader, LoadError> {
+        let header = Header::parse(header_buf)
+            .or_else(|| options.lenient.then(|| Header::parse_lenient(header_buf)).flatten())
+            .ok_or(LoadError::InvalidElf)?;
+
+        let mut image = Image::new(header_buf, bias, &options);
+        Self::map_segments(&header, &mut image, mmu, fetch)?;
+
+        if header.dynamic().is_none() && header.tls().is_none() {
+            return Self::link(header_buf, &header, image, mmu, ldso, stack, options);
+        }
+
+        // Rebuild the file layout of the segments
+        image.commit(mmu);
+        let len = header.phdrs.iter()
+            .filter(|ph| ph.ty == ProgramType::LOAD)
+            .map(|ph| ph.offset + ph.filesz)
+            .fold(header_buf.len(), usize::max);
+        let mut buf = vec![0; len];
+        buf[.. header_buf.len()].copy_from_slice(header_buf);
+        for ph in header.phdrs.iter().filter(|ph| ph.ty == ProgramType::LOAD) {
+            read_bytes(mmu, ph.vaddr + bias, &mut buf[ph.offset .. ph.offset + ph.filesz]);
+        }
+        Self::link(&buf, &header, image, mmu, ldso, stack, options)
+    }
+
+    /**
+     * Validate and map the PT_LOAD segments, with their content produced by `fetch` from file offsets.
+     * Each run from `segment_runs` is mapped as a single region.
+     */
+    fn map_segments<M: MMU>(header: &Header, image: &mut Image<M::AllocatedPage>, mmu: &mut M, fetch: &mut dyn FnMut(usize, &mut [u8])) -> Result<(), LoadError> {
+        for (vaddr, perm, offset, filesz) in Self::segment_runs(header, image.bias)? {
+            image.map_region_with(mmu, vaddr, perm, offset, filesz, &mut |at, dst| fetch(offset + at, dst));
+        }
+        Ok(())
+    }
+
+    /**
+     * Validate the PT_LOAD segments, and merge consecutive ones with the same permission,
+     * contiguous both in memory and in the file, into runs, with vaddrs offset by `bias`
+     */
+    fn segment_runs(header: &Header, bias: usize) -> Result<Vec<SegmentRun>, LoadError> {
+        let mut segments: Vec<SegmentRun> = Vec::new();
+        for ph in header.phdrs.iter() {
+            if ph.ty != ProgramType::LOAD {
+                continue;
+            }
+
+            if ph.filesz > ph.memsz {
+                return Err(LoadError::BadSegmentSizes);
+            }
+
+            // An alignment of 0 or 1 means no constraint
+            if ph.align > 1 && ph.vaddr % ph.align != ph.offset % ph.align {
+                return Err(LoadError::BadSegmentAlignment);
+            }
+            if ph.align > 1 && !bias.is_multiple_of(ph.align) {
+                return Err(LoadError::MisalignedBias);
+            }
+
+            // Later uses of the segment rely on its ranges not overflowing
+            let vaddr = ph.vaddr.checked_add(bias).ok_or(LoadError::InvalidElf)?;
+            if ph.file_range().is_none() || vaddr.checked_add(ph.memsz).is_none() {
+                return Err(LoadError::InvalidElf);
+            }
+            let perm = Perm {
+                r: ph.flags.contains(ProgramHeaderFlags::READ),
+                w: ph.flags.contains(ProgramHeaderFlags::WRITE),
+                x: ph.flags.contains(ProgramHeaderFlags::EXECUTE),
+            };
+
+            match segments.last_mut() {
+                // The previous segment must be fully file-backed, or its zero-filled tail would
+                // be overwritten by this one's content
+                Some((prev, prev_perm, prev_offset, prev_filesz)) if *prev_perm == perm
+                    && prev.end == vaddr && prev.len() == *prev_filesz && *prev_offset + *prev_filesz == ph.offset => {
+                    prev.end += ph.memsz;
+                    *prev_filesz += ph.filesz;
+                },
+                _ => segments.push((vaddr .. vaddr + ph.memsz, perm, ph.offset, ph.filesz)),
+            }
+        }
+        Ok(segments)
+    }
+
+    /**
+     * Rebuild an address space from a captured layout instead of an ELF, e.g. for checkpoint/restore.
+     * Each range is mapped with its permission and filled with the saved bytes followed by zeros.
+     * Stack pages not covered by the layout are freshly allocated, and a randomized stack picks
+     * its top with `entropy`, like `LoadOptions::entropy`. Nothing is relocated, and `entry` is
+     * left as zero.
+     */
+    pub fn restore<M: MMU>(layout: &[(Range<usize>, Perm, &[u8])], mmu: &mut M, stack: StackConfig, entropy: Option<&mut dyn FnMut() -> u64>) -> Result<Loader, LoadError> {
+        let mut image = Image::new(&[], 0, &LoadOptions::default());
+        for (vaddr, perm, content) in layout {
+            if content.len() > vaddr.len() {
+                return Err(LoadError::BadSegmentSizes);
+            }
+            image.map_region(mmu, vaddr.clone(), *perm, content);
+        }
+        image.commit(mmu);
+
+        let data_end = image.data_end();
+        let stack_start = stack.start_above(VirtAddr::from(VirtPageNum(image.place_above(0, &[], 0))).0, entropy)?;
+        let stack_vpns = match stack_start {
+            Some(start) => VirtAddr(start).floor().number() .. VirtAddr(start + stack.size).ceil().number(),
+            None => {
+                let pages = VirtAddr(stack.size).ceil().number();
+                let vpn = image.place_above(pages, &[], 0);
+                vpn .. vpn + pages
+            },
+        };
+        let stack_range = VirtAddr::from(VirtPageNum(stack_vpns.start)).0 .. VirtAddr::from(VirtPageNum(stack_vpns.end)).0;
+        let stack_top = stack_start.map_or(stack_range.end, |start| start + stack.size);
+        image.map_stack(mmu, stack_vpns);
+
+        Ok(Loader {
+            entry: 0,
+            bias: 0,
+            phdr: None,
+            entry_perm: NO_ACCESS,
+            data_end,
+            relro: None,
+            relocations: 0,
+            had_wx_window: image.has_wx(),
+            weak_undefined: Vec::new(),
+            regions: image.regions,
+            vdso_base: None,
+            stack: stack_range,
+            stack_top,
+            tls: None,
+            tls_module: None,
+            attributes: None,
+            abi: None,
+            symbols: BTreeMap::new(),
+            mapped: image.mapped,
+            pending: Vec::new(),
+            pending_perm: BTreeMap::new(),
+            skipped_relocs: Vec::new(),
+            reloc_index: BTreeMap::new(),
+            vdso_slots: Vec::new(),
+            vdso_len: 0,
+        })
+    }
+
+    /**
+     * Compute the bias to pass to `load_segments` so that the lowest PT_LOAD segment starts
+     * exactly at `desired_base`. Fails with `LoadError::NoSpace` if `desired_base` is below the
+     * segment's `p_vaddr`, as the bias would be negative.
+     */
+    pub fn bias_for_base(buf: &[u8], desired_base: usize) -> Result<usize, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let lowest = header.phdrs.iter()
+            .filter(|ph| ph.ty == ProgramType::LOAD)
+            .map(|ph| ph.vaddr)
+            .min()
+            .ok_or(LoadError::InvalidElf)?;
+        desired_base.checked_sub(lowest).ok_or(LoadError::NoSpace)
+    }
+
+    /**
+     * Size of the address space spanned by the PT_LOAD segments, from the start of the lowest
+     * page to the end of the highest one, gaps included. Nothing is mapped.
+     */
+    pub fn image_span(buf: &[u8]) -> Result<usize, LoadError> {
+        let pages = segment_pages(buf)?;
+        pages.len().checked_mul(PAGE_SIZE).ok_or(LoadError::InvalidElf)
+    }
+
+    /**
+     * Stable 64-bit hash of what loading `buf` depends on, e.g. to key a prelink cache when the
+     * ELF has no build-id. It covers the ELF type, `e_entry` and `e_flags`, then in program header
+     * order the type, flags, vaddr and sizes of PT_LOAD, PT_DYNAMIC, PT_TLS and PT_GNU_RELRO
+     * segments, together with the file bytes of PT_LOAD ones. File offsets, section headers and
+     * anything outside the loaded segments (symbol tables, debug info) are left out, so they
+     * may change without changing the hash. Nothing is mapped.
+     */
+    pub fn image_hash(buf: &[u8]) -> Result<u64, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let mut hash = Fnv64::default();
+        hash.update(&[(header.elf_type == ElfType::ET_DYN) as u8]);
+        hash.update_usize(header.entry);
+        hash.update(&header.flags.to_le_bytes());
+
+        let hashed = [ProgramType::LOAD, ProgramType::DYNAMIC, PT_TLS, PT_GNU_RELRO];
+        for ph in header.phdrs.iter() {
+            let Some(kind) = hashed.iter().position(|ty| *ty == ph.ty) else { continue };
+            hash.update(&[kind as u8]);
+            hash.update(&ph.flags.bits().to_le_bytes());
+            hash.update_usize(ph.vaddr);
+            hash.update_usize(ph.filesz);
+            hash.update_usize(ph.memsz);
+            if ph.ty == ProgramType::LOAD {
+                let content = ph.offset.checked_add(ph.filesz).and_then(|end| buf.get(ph.offset .. end));
+                hash.update(content.ok_or(LoadError::InvalidElf)?);
+            }
+        }
+        Ok(hash.finish())
+    }
+
+    /**
+     * Summarize `buf` from its headers and dynamic array alone, e.g. to triage many binaries
+     * before loading some. Nothing is mapped, and segment contents are only read for the
+     * interpreter path and the dynamic array.
+     */
+    pub fn inspect(buf: &[u8]) -> Result<ElfInfo<'_>, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let dynamic = header.dynamic()
+            .map(|range| Dynamic::parse(buf, range, &FileMap::new(&header.phdrs)).ok_or(LoadError::BadRelocTable))
+            .transpose()?
+            .map(|dynamic| dynamic.info());
+        let interpreter = header.phdrs.iter()
+            .find(|ph| ph.ty == ProgramType::INTERP)
+            .map(|ph| {
+                let path = ph.offset.checked_add(ph.filesz).and_then(|end| buf.get(ph.offset .. end)).ok_or(LoadError::InvalidElf)?;
+                Ok(path.split(|byte| *byte == 0).next().unwrap_or(path))
+            })
+            .transpose()?;
+
+        let pie = interpreter.is_some() || dynamic.as_ref().is_some_and(|info| info.flags_1 & DF_1_PIE != 0);
+        let kind = match header.elf_type {
+            ElfType::ET_EXEC => ElfKind::Executable,
+            ElfType::ET_DYN if pie => ElfKind::Pie,
+            ElfType::ET_DYN => ElfKind::SharedObject,
+            ElfType::ET_REL => ElfKind::Relocatable,
+            _ => ElfKind::Other,
+        };
+
+        Ok(ElfInfo {
+            kind,
+            entry: header.entry,
+            interpreter,
+            needed: dynamic.map_or(Vec::new(), |info| info.needed),
+            footprint: if header.phdrs.iter().any(|ph| ph.ty == ProgramType::LOAD) { Self::image_span(buf)? } else { 0 },
+        })
+    }
+
+    /**
+     * Entries of the PT_NOTE segments, as `(name, type, desc)`, for callers interpreting notes
+     * the loader doesn't, e.g. vendor notes. Nothing is mapped.
+     */
+    pub fn notes(buf: &[u8]) -> Result<Vec<Note<'_>>, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        Ok(header.notes(buf).collect())
+    }
+
+    /**
+     * Pick a random bias to pass to `load_segments`, a multiple of `required_base_align`, so that
+     * the whole image lands within `range`. The same `entropy` output always yields the same bias.
+     */
+    pub fn random_bias(buf: &[u8], range: Range<usize>, entropy: &mut dyn FnMut() -> u64) -> Result<usize, LoadError> {
+        let Range { start: lowest, end: highest } = segment_pages(buf)?;
+        let align = Self::required_base_align(buf)? / PAGE_SIZE;
+        let first = VirtAddr(range.start).ceil().number();
+        let last = VirtAddr(range.end).floor().number();
+        // Bounds of the bias, in pages
+        let low = first.checked_sub(lowest).ok_or(LoadError::NoSpace)?.next_multiple_of(align);
+        let high = last.checked_sub(highest).ok_or(LoadError::NoSpace)?;
+        let slots = high.checked_sub(low).ok_or(LoadError::NoSpace)? / align + 1;
+        let bias = low + (entropy() % slots as u64) as usize * align;
+        Ok(VirtAddr::from(VirtPageNum(bias)).0)
+    }
+
+    /**
+     * Alignment the bias passed to `load_segments` must have: the largest `p_align` of the
+     * PT_LOAD segments, and at least a page. Nothing is mapped.
+     */
+    pub fn required_base_align(buf: &[u8]) -> Result<usize, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        Ok(header.phdrs.iter()
+            .filter(|ph| ph.ty == ProgramType::LOAD)
+            .map(|ph| ph.align)
+            .fold(PAGE_SIZE, usize::max))
+    }
+
+    /**
+     * Resolve every dynamic relocation without loading anything, collecting the problems that
+     * would prevent them from being applied. Undefined symbols are looked up through `resolve`.
+     * Reported offsets are relative to the ELF's link-time addresses.
+     */
+    pub fn check_relocations<F: FnMut(&[u8]) -> Option<usize>>(buf: &[u8], mut resolve: F) -> Result<Vec<RelocIssue>, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let file_map = FileMap::new(&header.phdrs);
+        let dynamic = match header.dynamic() {
+            Some(range) => Dynamic::parse(buf, range, &file_map).ok_or(LoadError::BadRelocTable)?,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut issues = Vec::new();
+        for ent in dynamic.relas() {
+            let in_image = header.phdrs.iter()
+                .any(|ph| ph.ty == ProgramType::LOAD && ph.mem_range().is_some_and(|range| range.contains(&ent.offset)));
+            if !in_image {
+                issues.push(RelocIssue::OutOfRange { offset: ent.offset });
+                continue;
+            }
+
+            if let Err(issue) = relocate_dynamic(ent, &dynamic, 0, 1, &mut resolve) {
+                issues.push(issue);
+            }
+        }
+        Ok(issues)
+    }
+
+    /**
+     * Whether the ELF needs a dynamic linker beyond this loader: it depends on shared libraries
+     * (`DT_NEEDED`), or has relocations this loader can't apply by itself, e.g. against undefined
+     * symbols. Self-relocating PIEs with only relative relocations don't.
+     */
+    pub fn needs_interpreter(buf: &[u8]) -> Result<bool, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let dynamic = match header.dynamic() {
+            Some(range) => Dynamic::parse(buf, range, &FileMap::new(&header.phdrs)).ok_or(LoadError::BadRelocTable)?,
+            None => return Ok(false),
+        };
+        if dynamic.needed > 0 {
+            return Ok(true);
+        }
+        if let Some(RelTable::REL(_)) = &dynamic.rel {
+            return Ok(true);
+        }
+        Ok(dynamic.relas().any(|ent| relocate_dynamic(ent, &dynamic, 0, 1, &mut |_| None).is_err()))
+    }
+
+    /**
+     * Apply a single dynamic relocation of an object loaded with `bias`, through the relocation
+     * dispatcher used when loading. Symbols are looked up in `dynamic`, and undefined ones through
+     * `resolve`. TLS relocations are resolved as for the main program, TLS module 1. The patched
+     * slot must already be mapped.
+     */
+    pub fn apply_reloc_entry<M: MMU, F: FnMut(&[u8]) -> Option<usize>>(ent: &Elf64RELA, dynamic: &Dynamic, bias: usize, mut resolve: F, mmu: &mut M) -> Result<RelocOutcome, LoadError> {
+        let fixup = relocate_dynamic(ent, dynamic, bias, 1, &mut resolve).map_err(|issue| match issue {
+            RelocIssue::Unresolved { .. } => LoadError::UnresolvedSymbol,
+            RelocIssue::OutOfRange { .. } => LoadError::RelocOutOfRange,
+            RelocIssue::Unsupported { ty, .. } => LoadError::UnsupportedReloc { ty },
+            RelocIssue::Overflow { ty, .. } => LoadError::RelocOverflow { ty },
+        })?;
+        let Some(fixup) = fixup else { return Ok(RelocOutcome::Skipped) };
+        if mmu.translate(fixup.vaddr).is_none() {
+            return Err(LoadError::RelocOutOfRange);
+        }
+        fixup.apply(mmu);
+        Ok(RelocOutcome::Applied { vaddr: fixup.vaddr, value: fixup.value })
+    }
+
+    /**
+     * Collect the distinct names of non-weak undefined symbols that `resolve` doesn't know into
+     * `out`, in relocation order, for reporting without allocating. Names are borrowed from `buf`.
+     * Returns the number of names written, and whether more didn't fit.
+     */
+    pub fn unresolved_symbols<'b, F: FnMut(&[u8]) -> Option<usize>>(buf: &'b [u8], mut resolve: F, out: &mut [&'b [u8]]) -> Result<(usize, bool), LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let file_map = FileMap::new(&header.phdrs);
+        let dynamic = match header.dynamic() {
+            Some(range) => Dynamic::parse(buf, range, &file_map).ok_or(LoadError::BadRelocTable)?,
+            None => return Ok((0, false)),
+        };
+
+        let mut written = 0;
+        let mut truncated = false;
+        for ent in dynamic.relas() {
+            let Some((name, false)) = unresolved_symbol(ent, &dynamic, &mut resolve) else { continue };
+            if out[.. written].contains(&name) {
+                continue;
+            }
+            match out.get_mut(written) {
+                Some(slot) => {
+                    *slot = name;
+                    written += 1;
+                },
+                None => truncated = true,
+            }
+        }
+        Ok((written, truncated))
+    }
+
+    /**
+     * Resolve the symbols of every dynamic relocation against the VDSO described by `config`
+     * without loading anything, collecting the non-weak undefined ones it doesn't export, in
+     * relocation order. Empty if loading with `config` leaves nothing unresolved.
+     */
+    pub fn check_vdso<F: FnMut(&[u8]) -> Option<usize>>(buf: &[u8], config: &mut VDSOConfig<F>) -> Result<Vec<MissingSymbol>, LoadError> {
+        let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+        let dynamic = match header.dynamic() {
+            Some(range) => Dynamic::parse(buf, range, &FileMap::new(&header.phdrs)).ok_or(LoadError::BadRelocTable)?,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut missing: Vec<MissingSymbol> = Vec::new();
+        for ent in dynamic.relas() {
+            let Some((name, false)) = unresolved_symbol(ent, &dynamic, &mut config.lookup) else { continue };
+            if name.is_empty() {
+                continue;
+            }
+            match missing.iter_mut().find(|symbol| symbol.name == name) {
+                Some(symbol) => symbol.offsets.push(ent.offset),
+                None => missing.push(MissingSymbol { name: name.to_vec(), offsets: vec![ent.offset] }),
+            }
+        }
+        Ok(missing)
+    }
+
+    /**
+     * Load a relocatable object (ET_REL), e.g. a kernel module, with its sections placed from `base`
+     * on. Undefined symbols are resolved to the address returned by `lookup`.
+     * Relocatable objects have no entry point, so `entry` is left as zero.
+     */
+    pub fn load_relocatable<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], mmu: &mut M, base: usize, mut lookup: F) -> Result<Loader, LoadError> {
+        if !TARGET.matches(buf) {
+            return Err(LoadError::InvalidElf);
+        }
+        let parsed = elf_rs::Elf64::from_bytes(buf).map_err(|_| LoadError::InvalidElf)?;
+
+        let mut image = Image::new(buf, base, &LoadOptions::default());
+        for region in link_relocatable(buf, &parsed, base, &mut lookup)? {
+            let vaddr = region.vaddr .. region.vaddr + region.content.len();
+            image.map_region(mmu, vaddr, region.perm, &region.content);
+        }
+        image.commit(mmu);
+
+        Ok(Loader {
+            entry: 0,
+            bias: image.bias,
+            phdr: None,
+            entry_perm: NO_ACCESS,
+            data_end: image.data_end(),
+            relro: None,
+            relocations: 0,
+            had_wx_window: image.has_wx(),
+            weak_undefined: Vec::new(),
+            regions: image.regions,
+            vdso_base: None,
+            stack: 0 .. 0,
+            stack_top: 0,
+            tls: None,
+            tls_module: None,
+            attributes: RiscvAttributes::find(buf),
+            abi: Header::parse(buf).map(|header| riscv_abi(header.flags)),
+            symbols: BTreeMap::new(),
+            mapped: image.mapped,
+            pending: Vec::new(),
+            pending_perm: BTreeMap::new(),
+            skipped_relocs: Vec::new(),
+            reloc_index: BTreeMap::new(),
+            vdso_slots: Vec::new(),
+            vdso_len: 0,
+        })
+    }
+
+    /**
+     * Common part of loading after the image is mapped: VDSO, relocations and stack
+     */
+    fn link<M: MMU, F: for<'r> FnMut(&'r [u8]) -> Option<usize>>(buf: &[u8], header: &Header, mut image: Image<M::AllocatedPage>, mmu: &mut M, mut ldso: Option<VDSOConfig<F>>, stack: StackConfig, mut options: LoadOptions) -> Result<Loader, LoadError> {
+        if options.null_guard && image.mapped.contains_key(&0) {
+            return Err(LoadError::NullPage);
+        }
+        // Pages lying entirely within the user space
+        let user_vpns = VirtAddr(options.user_space.start).ceil().number() .. VirtAddr(options.user_space.end).floor().number();
+        let in_user_space = |vpns: &Range<usize>| vpns.is_empty() || (user_vpns.start <= vpns.start && vpns.end <= user_vpns.end);
+        if !image.mapped.keys().all(|vpn| user_vpns.contains(vpn)) {
+            return Err(LoadError::OutsideUserSpace);
+        }
+        let guard_pages: Vec<usize> = match options.per_segment_guard {
+            true => Self::segment_runs(header, image.bias)?.iter().map(|(vaddr, ..)| VirtAddr(vaddr.end).ceil().number()).collect(),
+            false => Vec::new(),
+        };
+        if guard_pages.iter().any(|vpn| image.mapped.contains_key(vpn)) {
+            return Err(LoadError::GuardPageMapped);
+        }
+
+        // Locate the dynamic array through PT_DYNAMIC, so that the vaddrs it contains are
+        // interpreted against the same segment layout the relocation offsets refer to
+        // Static executables have no PT_DYNAMIC, and skip relocation entirely
+        let file_map = FileMap::new(&header.phdrs);
+        let dynamic = header.dynamic()
+            .map(|range| Dynamic::parse(buf, range, &file_map).ok_or(LoadError::BadRelocTable))
+            .transpose()?;
+        let declared = match dynamic.as_ref() {
+            Some(Dynamic { rel: Some(RelTable::REL(tbl)), .. }) => tbl.len(),
+            Some(dynamic) => dynamic.relas().count(),
+            None => 0,
+        };
+        if options.max_relocations.is_some_and(|max| declared > max) {
+            return Err(LoadError::TooManyRelocations);
+        }
+        image.commit(mmu);
+
+        let mut tls = match header.tls() {
+            Some(ph) if ph.filesz > ph.memsz => return Err(LoadError::BadSegmentSizes),
+            Some(ph) => {
+                // .tdata is copied from the file, .tbss only contributes zeros up to p_memsz
+                let mut image = ph.file_range().and_then(|range| buf.get(range)).ok_or(LoadError::InvalidElf)?.to_vec();
+                image.resize(ph.memsz, 0);
+                Some(TlsTemplate { image, align: ph.align.max(1) })
+            },
+            None => None,
+        };
+
+        // Map VDSO text. Symbol offsets are taken from `start`, so it must coincide with the first
+        // mapped page
+        if let Some(config) = &ldso {
+            let aligned = |addr: usize| addr.is_multiple_of(PAGE_SIZE);
+            if config.end <= config.start || !aligned(config.start) || !config.target.is_none_or(aligned) {
+                return Err(LoadError::BadVDSORange);
+            }
+        }
+        let mut vdso_pages = Vec::new();
+        let mut occupied: Vec<Range<usize>> = options.reserved_ranges.iter()
+            .map(|(start, end)| VirtAddr(*start).floor().number() .. VirtAddr(*end).ceil().number())
+            .collect();
+        let gap = options.min_gap_pages;
+        let image_end = VirtAddr::from(VirtPageNum(image.place_above(0, &[], gap))).0;
+        let stack_start = stack.start_above(image_end, options.entropy.as_mut().map(|entropy| &mut **entropy as &mut dyn FnMut() -> u64))?;
+        let fixed_stack = stack_start.map(|start| VirtAddr(start).floor().number() .. VirtAddr(start + stack.size).ceil().number());
+        if fixed_stack.as_ref().is_some_and(|vpns| !in_user_space(vpns)) {
+            return Err(LoadError::OutsideUserSpace);
+        }
+        if fixed_stack.as_ref().is_some_and(|vpns| guard_pages.iter().any(|vpn| vpns.contains(vpn))) {
+            return Err(LoadError::GuardPageMapped);
+        }
+        occupied.extend(guard_pages.iter().map(|vpn| *vpn .. vpn + 1));
+        occupied.extend(fixed_stack.clone());
+        if options.null_guard {
+            let vdso_at_null = ldso.as_ref().and_then(|config| config.target).is_some_and(|target| target < PAGE_SIZE);
+            if fixed_stack.as_ref().is_some_and(|vpns| vpns.contains(&0)) || vdso_at_null {
+                return Err(LoadError::NullPage);
+            }
+            occupied.push(0 .. 1);
+        }
+        let vdso_base = ldso.as_ref().map(|config| -> Result<usize, LoadError> {
+            let text_vdso_start_ppn = PhysAddr(config.start).floor().0;
+            let text_vdso_end_ppn = PhysAddr(config.end).ceil().0;
+            let target = config.target.unwrap_or_else(|| {
+                if let Some(entropy) = options.entropy.as_mut() {
+                    // Keep the skipped pages free, so the VDSO is placed above them
+                    let slide = (entropy() % VDSO_SLIDE_PAGES as u64) as usize;
+                    let above = image.place_above(0, &[], 0);
+                    occupied.push(above .. above + slide);
+                }
+                // Align the vpn like the start ppn, up to the largest level the range can use,
+                // so that huge pages remain possible
+                let pages = text_vdso_end_ppn - text_vdso_start_ppn;
+                let align = match mmu.supports_huge_pages() {
+                    true => level_pages(mapping_level(text_vdso_start_ppn, 0, pages)),
+                    false => 1,
+                };
+                let vpn = image.place_aligned(pages, align, &occupied, gap);
+                VirtAddr::from(VirtPageNum(vpn)).0
+            });
+            let text_vdso_start_vpn = VirtAddr(target).floor().0;
+            let vdso_vpns = text_vdso_start_vpn .. text_vdso_start_vpn + (text_vdso_end_ppn - text_vdso_start_ppn);
+            if !in_user_space(&vdso_vpns) {
+                return Err(LoadError::OutsideUserSpace);
+            }
+            if guard_pages.iter().any(|vpn| vdso_vpns.contains(vpn)) {
+                return Err(LoadError::GuardPageMapped);
+            }
+
+            let perms = vdso_page_perms(config.start, config.end);
+
+            let mut leaves = Vec::new();
+            let mut ppn = text_vdso_start_ppn;
+            while ppn < text_vdso_end_ppn {
+                let pcount = ppn - text_vdso_start_ppn;
+                let vpn = text_vdso_start_vpn + pcount;
+                let perm = perms[pcount];
+                let run = perms[pcount ..].iter().take_while(|p| **p == perm).count();
+                let level = mapping_level(ppn, vpn, run);
+                leaves.push((ppn, vpn, perm, level));
+                ppn += level_pages(level);
+            }
+            if options.require_huge && (!mmu.supports_huge_pages() || leaves.iter().any(|(_, _, _, level)| *level == 0)) {
+                return Err(LoadError::HugePageUnsupported);
+            }
+            for (ppn, vpn, perm, level) in leaves {
+                mmu.map_at_level(ppn, vpn, perm, level);
+                vdso_pages.extend((vpn .. vpn + level_pages(level)).map(|vpn| (vpn, perm)));
+            }
+            occupied.push(vdso_vpns);
+
+            Ok(target)
+        }).transpose()?;
+
+        // Fixup GOT. Relocations against symbols neither defined by the image, overridden,
+        // provided by the VDSO nor by `options.lookup`, and unsupported ones when not strict, are
+        // left for the program's own dynamic linker.
+        let overrides = options.symbol_overrides;
+        let fallback = &mut options.lookup;
+        let mut resolve = |name: &[u8]| match overrides.iter().find(|(symbol, _)| *symbol == name) {
+            Some((_, vaddr)) => Some(*vaddr),
+            None => match (&mut ldso, vdso_base) {
+                (Some(config), Some(vdso_base)) => (config.lookup)(name).map(|at| vdso_base + (at - config.start)),
+                _ => None,
+            }.or_else(|| fallback.as_mut().and_then(|lookup| lookup(name))),
+        };
+        let mut fixups = Vec::new();
+        let mut relocations = 0;
+        let mut skipped_relocs = Vec::new();
+        let mut weak_undefined: Vec<Vec<u8>> = Vec::new();
+        if let Some(dynamic) = &dynamic {
+            // GOT[0] is the first slot of .got. DT_PLTGOT names .got.plt instead, whose first slot
+            // is reserved for the lazy resolver, so it is only used without section headers.
+            // Either way, only a slot holding the link-time _DYNAMIC is updated.
+            let dynamic_vaddr = header.dynamic_vaddr();
+            let got = section_vaddr(buf, b".got").or(dynamic.pltgot)
+                .filter(|got| !options.preserve_got0 && file_map.read_word(buf, *got) == dynamic_vaddr);
+            if let (Some(got), Some(dynamic_vaddr)) = (got, dynamic_vaddr) {
+                let fixup = Fixup::word(image.bias + got, image.bias + dynamic_vaddr, Some(dynamic_vaddr));
+                if !image.mapped.contains_key(&VirtAddr(fixup.vaddr).floor().number()) {
+                    return Err(LoadError::RelocOutOfRange);
+                }
+                fixups.push(fixup);
+            }
+
+            // RISC-V only uses RELA, so REL relocations are never implemented
+            if let Some(RelTable::REL(tbl)) = &dynamic.rel {
+                if let Some(ent) = tbl.first() {
+                    return Err(LoadError::UnsupportedReloc { ty: ent.reloc_type() });
+                }
+            }
+            for ent in dynamic.relas() {
+                let fixup = match relocate_dynamic(ent, dynamic, image.bias, options.tls_module, &mut resolve) {
+                    Err(RelocIssue::Unsupported { ty, .. }) if options.strict_relocs => return Err(LoadError::UnsupportedReloc { ty }),
+                    Err(RelocIssue::Overflow { ty, .. }) => return Err(LoadError::RelocOverflow { ty }),
+                    Err(RelocIssue::Unsupported { offset, ty }) => {
+                        skipped_relocs.push((offset, ty));
+                        None
+                    },
+                    Ok(fixup) => fixup,
+                    Err(_) => None,
+                };
+                if options.record_weak_undef {
+                    if let Some((name, true)) = unresolved_symbol(ent, dynamic, &mut resolve) {
+                        if !weak_undefined.iter().any(|weak| weak == name) {
+                            weak_undefined.push(name.to_vec());
+                        }
+                    }
+                }
+                if let Some(fixup) = fixup {
+                    relocations += 1;
+                    // Pointers inside .tdata belong to the template every thread is
+                    // initialized from, not to the copy mapped in the image
+                    if let (Some(ph), Some(template)) = (header.tls(), tls.as_mut()) {
+                        if ph.mem_range().is_some_and(|range| range.contains(&ent.offset)) {
+                            let at = ent.offset - ph.vaddr;
+                            let field = template.image.get_mut(at .. at + fixup.width)
+                                .ok_or(LoadError::RelocOutOfRange)?;
+                            field.copy_from_slice(&(fixup.value as u64).to_le_bytes()[.. fixup.width]);
+                            continue;
+                        }
+                    }
+
+                    if !image.mapped.contains_key(&VirtAddr(fixup.vaddr).floor().number()) {
+                        return Err(LoadError::RelocOutOfRange);
+                    }
+                    fixups.push(fixup);
+                }
+            }
+        }
+
+        let vdso_len = vdso_pages.len() * PAGE_SIZE;
+        let vdso_slots = match vdso_base {
+            Some(base) => fixups.iter()
+                .filter(|fixup| fixup.width == core::mem::size_of::<usize>() && (base .. base + vdso_len).contains(&fixup.value))
+                .map(|fixup| fixup.vaddr)
+                .collect(),
+            None => Vec::new(),
+        };
+        let had_wx_window = image.has_wx() || fixups.iter()
+            .any(|fixup| image.mapped.get(&VirtAddr(fixup.vaddr).floor().number()).is_some_and(|perm| perm.x));
+        if options.check_prevalues {
+            if let Some(fixup) = fixups.iter().find(|fixup| !fixup.check(mmu)) {
+                return Err(LoadError::UnexpectedPrevalue { vaddr: fixup.vaddr });
+            }
+        }
+
+        let mut pending = Vec::new();
+        let mut pending_perm = BTreeMap::new();
+        // Objects linked with `-z now` expect to be fully relocated before running
+        let lazy = options.lazy_relocation && !dynamic.as_ref().is_some_and(Dynamic::bind_now);
+        if lazy {
+            // Revoke all access to pages with relocations, so the first access faults
+            fixups.sort_by_key(|fixup| fixup.vaddr);
+            for fixup in fixups.iter() {
+                let vpn = VirtAddr(fixup.vaddr).floor().number();
+                if let Some(perm) = image.mapped.get(&vpn) {
+                    if pending_perm.insert(vpn, *perm).is_none() {
+                        let ppn = PhysAddr(mmu.translate(fixup.vaddr).unwrap()).floor().0;
+                        mmu.map_existing(ppn, vpn, NO_ACCESS);
+                    }
+                }
+            }
+            pending = fixups;
+        } else {
+            for fixup in fixups.iter() {
+                // A stale or wrong translation would have the write corrupt an unrelated frame
+                let vpn = VirtAddr(fixup.vaddr).floor().number();
+                debug_assert_eq!(mmu.translate(fixup.vaddr).map(|paddr| PhysAddr(paddr).floor().0), image.ppns.get(&vpn).copied(),
+                    "MMU translates relocated vaddr {:#x} outside of the frame mapped for it", fixup.vaddr);
+                fixup.apply(mmu);
+            }
+
+            if options.assert_got_complete {
+                let word = core::mem::size_of::<usize>();
+                for slots in got_slots(buf) {
+                    for offset in slots.step_by(word) {
+                        let vaddr = image.bias + offset;
+                        let Some(paddr) = mmu.translate(vaddr) else { continue };
+                        let zero = unsafe { read_field(paddr, word) } == 0;
+                        if zero && !fixups.iter().any(|fixup| fixup.vaddr == vaddr && fixup.value == 0) && !got_slot_may_be_zero(dynamic.as_ref(), offset) {
+                            return Err(LoadError::IncompleteGot { offset });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Write-protect RELRO once relocated. Deferred pages are protected when restored.
+        let relro = header.relro().map(|ph| {
+            let vaddr = image.bias.checked_add(ph.vaddr).ok_or(LoadError::InvalidElf)?;
+            let end = vaddr.checked_add(ph.memsz).and_then(|end| end.checked_next_multiple_of(PAGE_SIZE)).ok_or(LoadError::InvalidElf)?;
+            Ok(VirtAddr::from(VirtAddr(vaddr).floor()).0 .. end)
+        }).transpose()?;
+        if options.protect_relro {
+            if let Some(relro) = &relro {
+                for vpn in VirtAddr(relro.start).floor().number() .. VirtAddr(relro.end).ceil().number() {
+                    let Some(perm) = image.mapped.get_mut(&vpn) else { continue };
+                    perm.w = false;
+                    match pending_perm.get_mut(&vpn) {
+                        Some(pending) => pending.w = false,
+                        None => {
+                            let ppn = PhysAddr(mmu.translate(VirtAddr::from(VirtPageNum(vpn)).0).unwrap()).floor().0;
+                            mmu.map_existing(ppn, vpn, *perm);
+                        },
+                    }
+                }
+            }
+        }
+
+        // Allocate stack
+        let stack_vpns = fixed_stack.unwrap_or_else(|| {
+            let pages = VirtAddr(stack.size).ceil().number();
+            let vpn = image.place_above(pages, &occupied, gap);
+            vpn .. vpn + pages
+        });
+        if !in_user_space(&stack_vpns) {
+            return Err(LoadError::OutsideUserSpace);
+        }
+        let stack_range = VirtAddr::from(VirtPageNum(stack_vpns.start)).0 .. VirtAddr::from(VirtPageNum(stack_vpns.end)).0;
+        let stack_top = stack_start.map_or(stack_range.end, |start| start + stack.size);
+
+        let data_end = image.data_end();
+        image.map_stack(mmu, stack_vpns);
+        let mut mapped = image.mapped;
+        mapped.extend(vdso_pages);
+
+        let entry = image.bias + header.entry;
+        let entry_perm = mapped.get(&VirtAddr(entry).floor().number()).copied().unwrap_or(NO_ACCESS);
+
+        let symbols = dynamic.iter()
+            .flat_map(|dynamic| dynamic.exports(image.bias))
+            .map(|(name, vaddr, _)| (name.as_bytes().to_vec(), vaddr))
+            .collect();
+
+        Ok(Loader {
+            entry,
+            bias: image.bias,
+            phdr: header.phdr_vaddr().map(|vaddr| image.bias + vaddr),
+            entry_perm,
+            regions: image.regions,
+            vdso_base,
+            stack: stack_range,
+            stack_top,
+            tls_module: tls.as_ref().map(|_| options.tls_module),
+            tls,
+            attributes: RiscvAttributes::find(buf),
+            abi: Some(riscv_abi(header.flags)),
+            data_end,
+            relro,
+            relocations,
+            had_wx_window,
+            weak_undefined,
+            symbols,
+            mapped,
+            reloc_index: index_by_page(&pending),
+            vdso_slots,
+            vdso_len,
+            pending,
+            pending_perm,
+            skipped_relocs,
+        })
+    }
+
+    /**
+     * Process vaddr of a symbol exported by the loaded image, looked up by name
+     */
+    pub fn symbol_vaddr(&self, name: &[u8]) -> Option<usize> {
+        self.symbols.get(name).copied()
+    }
+
+    /**
+     * Describe every mapping of the process as sorted entries, coalescing runs of pages contiguous
+     * in both vaddr and paddr with the same permission, for page table builders working from a
+     * layout instead of individual `map` calls. Permissions are the final ones, after relocation.
+     */
+    pub fn plan<M: MMU>(&self, mmu: &M) -> Vec<LayoutEntry> {
+        let mut layout: Vec<LayoutEntry> = Vec::new();
+        for (vpn, perm) in self.mapped.iter() {
+            let vaddr = VirtAddr::from(VirtPageNum(*vpn)).0;
+            let ppn = PhysAddr(mmu.translate(vaddr).unwrap()).floor().0;
+            match layout.last_mut() {
+                Some(last) if last.vaddr.end == vaddr && last.ppn.end == ppn && last.perm == *perm => {
+                    last.vaddr.end += PAGE_SIZE;
+                    last.ppn.end += 1;
+                },
+                _ => layout.push(LayoutEntry { vaddr: vaddr .. vaddr + PAGE_SIZE, ppn: ppn .. ppn + 1, perm: *perm }),
+            }
+        }
+        layout
+    }
+
+    /**
+     * Describe every mapping of the process with what backs it, bias applied, as sorted entries
+     * coalescing runs of pages with the same permission and backing, e.g. to emulate
+     * `/proc/pid/maps`. A page holding any file content counts as file-backed. Permissions are
+     * the final ones, after relocation.
+     */
+    pub fn maps(&self) -> Vec<MapEntry> {
+        let vdso = self.vdso_base.map_or(0 .. 0, |base| base .. base + self.vdso_len);
+        let mut maps: Vec<MapEntry> = Vec::new();
+        for (vpn, perm) in self.mapped.iter() {
+            let vaddr = VirtAddr::from(VirtPageNum(*vpn)).0;
+            let page = vaddr .. vaddr + PAGE_SIZE;
+            let kind = if vdso.contains(&vaddr) {
+                MapKind::Vdso
+            } else if self.stack.contains(&vaddr) {
+                MapKind::Stack
+            } else {
+                self.regions.iter()
+                    .find(|region| region.file_size > 0 && region.vaddr.start < page.end && page.start < region.vaddr.start + region.file_size)
+                    .map_or(MapKind::Anon, |region| MapKind::File { offset: (region.file_offset + vaddr).wrapping_sub(region.vaddr.start) })
+            };
+
+            let continues = |last: &MapEntry| match (last.kind, kind) {
+                (MapKind::File { offset: last_offset }, MapKind::File { offset }) => last_offset + last.vaddr.len() == offset,
+                (last_kind, kind) => last_kind == kind,
+            };
+            match maps.last_mut() {
+                Some(last) if last.vaddr.end == vaddr && last.perm == *perm && continues(last) => last.vaddr.end += PAGE_SIZE,
+                _ => maps.push(MapEntry { vaddr: page, perm: *perm, kind }),
+            }
+        }
+        maps
+    }
+
+    /**
+     * Re-hash the live content of every region with a recorded CRC (see `LoadOptions::segment_crc`),
+     * returning false if any of them changed since loading.
+     */
+    pub fn verify_integrity<M: MMU>(&self, mmu: &M) -> bool {
+        self.regions.iter().all(|region| match region.crc {
+            Some(crc) => crc_bytes(mmu, region.vaddr.clone()) == crc,
+            None => true,
+        })
+    }
+
+    /**
+     * Indices into the deferred relocations of those targeting page `vpn`, empty if there are none
+     */
+    pub fn page_relocations(&self, vpn: usize) -> Range<usize> {
+        self.reloc_index.get(&VirtPageNum(vpn)).cloned().unwrap_or(0..0)
+    }
+
+    /**
+     * Whether page `vpn` still has deferred relocations, not applied by `relocate_page` yet
+     */
+    pub fn is_pending(&self, vpn: usize) -> bool {
+        self.pending_perm.contains_key(&vpn)
+    }
+
+    /**
+     * Adjust the relocated slots pointing into the VDSO after the caller moved it to `new_base`,
+     * e.g. GOT entries of VDSO functions. Deferred relocations are adjusted before being applied.
+     * Does nothing if no VDSO was linked.
+     */
+    pub fn rebind_vdso<M: MMU>(&mut self, mmu: &mut M, new_base: usize) {
+        let Some(old_base) = self.vdso_base else { return };
+        let delta = new_base.wrapping_sub(old_base);
+        for slot in self.vdso_slots.iter() {
+            let mut current = [0; core::mem::size_of::<usize>()];
+            read_bytes(mmu, *slot, &mut current);
+            Fixup::word(*slot, usize::from_le_bytes(current).wrapping_add(delta), None).apply(mmu);
+        }
+        for fixup in self.pending.iter_mut().filter(|fixup| self.vdso_slots.contains(&fixup.vaddr)) {
+            fixup.value = fixup.value.wrapping_add(delta);
+        }
+
+        let old_vpn = VirtAddr(old_base).floor().number();
+        let new_vpn = VirtAddr(new_base).floor().number();
+        let pages = self.vdso_len / PAGE_SIZE;
+        let perms: Vec<_> = (old_vpn .. old_vpn + pages).filter_map(|vpn| self.mapped.remove(&vpn)).collect();
+        self.mapped.extend((new_vpn ..).zip(perms));
+        self.vdso_base = Some(new_base);
+    }
+
+    /**
+     * Apply the deferred relocations targeting the page containing `vaddr`, and restore the
+     * page's permission. Meant to be called from the page fault handler when loaded with
+     * `LoadOptions::lazy_relocation`. Returns false if the page has no deferred relocations,
+     * including once they have been applied by an earlier call.
+     */
+    pub fn relocate_page<M: MMU>(&mut self, mmu: &mut M, vaddr: usize) -> bool {
+        let vpn = VirtAddr(vaddr).floor();
+        let perm = match self.pending_perm.remove(&vpn.number()) {
+            Some(perm) => perm,
+            None => return false,
+        };
+
+        let page_start = VirtAddr::from(vpn).0;
+        for fixup in self.pending[self.page_relocations(vpn.number())].iter() {
+            fixup.apply(mmu);
+        }
+
+        let ppn = PhysAddr(mmu.translate(page_start).unwrap()).floor().0;
+        mmu.map_existing(ppn, vpn.number(), perm);
+        true
+    }
+
+    /**
+     * Turn the process loaded in `mmu` into a template for `FrozenImage::instantiate`, applying
+     * deferred relocations first. Its frames become the template's, so writable pages are
+     * remapped read-only in `mmu` as well, which is then an instance like the others.
+     */
+    pub fn freeze<M: MMU>(mut self, mmu: &mut M) -> FrozenImage {
+        let deferred: Vec<usize> = self.pending_perm.keys().copied().collect();
+        for vpn in deferred {
+            self.relocate_page(mmu, VirtAddr::from(VirtPageNum(vpn)).0);
+        }
+        self.pending.clear();
+        self.pending_perm.clear();
+        self.reloc_index.clear();
+
+        let frames = self.mapped.iter()
+            .map(|(vpn, perm)| {
+                let ppn = PhysAddr(mmu.translate(VirtAddr::from(VirtPageNum(*vpn)).0).unwrap()).floor().0;
+                (*vpn, (ppn, *perm))
+            })
+            .collect();
+        let frozen = FrozenImage { loader: self, frames };
+        frozen.instantiate(mmu);
+        frozen
+    }
+}
+
+impl<P: Page> Image<P> {
+    fn new(buf: &[u8], bias: usize, options: &LoadOptions) -> Self {
+        let dma = match elf_rs::Elf64::from_bytes(buf) {
+            Ok(parsed) if !options.dma_sections.is_empty() && has_section_headers(buf) => parsed.section_header_iter()
+                .filter(|sec_hdr| sec_hdr.flags().contains(SectionHeaderFlags::SHF_ALLOC))
+                .filter(|sec_hdr| options.dma_sections.contains(&sec_hdr.section_name()))
+                .map(|sec_hdr| {
+                    let addr = sec_hdr.addr() as usize + bias;
+                    VirtAddr(addr).floor().number() .. VirtAddr(addr + sec_hdr.size() as usize).ceil().number()
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            bias,
+            mapped: BTreeMap::new(),
+            frames: BTreeMap::new(),
+            ppns: BTreeMap::new(),
+            regions: Vec::new(),
+            segment_crc: options.segment_crc,
+            dma,
+            dma_align: options.dma_align,
+            map_descending: options.map_descending,
+            contiguous: Vec::new(),
+        }
+    }
+
+    /**
+     * Map every filled frame with the final permission of its page, in `map_descending` order.
+     * Runs of frames allocated contiguously are mapped with one `map_range` call per permission.
+     */
+    fn commit<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M) {
+        // (first vpn, page count, first page, whether contiguously allocated)
+        let mut runs: Vec<(usize, usize, P, bool)> = Vec::new();
+        for (vpn, page) in core::mem::take(&mut self.frames) {
+            let contiguous = self.contiguous.iter().find(|run| run.contains(&vpn));
+            match runs.last_mut() {
+                Some((first, count, _, true)) if contiguous.is_some_and(|run| run.contains(first))
+                    && *first + *count == vpn && self.mapped[first] == self.mapped[&vpn] => *count += 1,
+                _ => runs.push((vpn, 1, page, contiguous.is_some())),
+            }
+        }
+
+        let mut map = |(vpn, count, page, contiguous): (usize, usize, P, bool)| {
+            let perm = self.mapped[&vpn];
+            let ppn = PhysAddr(page.inner().as_ptr() as usize).floor().0;
+            self.ppns.extend((0 .. count).map(|i| (vpn + i, ppn + i)));
+            if contiguous {
+                mmu.map_range(ppn, vpn, count, perm);
+            } else {
+                mmu.map(page, vpn, perm);
+            }
+        };
+        if self.map_descending {
+            runs.into_iter().rev().for_each(&mut map);
+        } else {
+            runs.into_iter().for_each(&mut map);
+        }
+    }
+
+    /// Map the stack over `vpns`, except for pages already mapped, e.g. by a restored layout
+    fn map_stack<M: MMU>(&mut self, mmu: &mut M, vpns: Range<usize>) {
+        // TODO: extendable stack
+        let stack_perm = Perm {
+            r: true,
+            w: true,
+            x: false,
+        };
+
+        let mut map = |stack_vpn| {
+            if self.mapped.contains_key(&stack_vpn) {
+                return;
+            }
+            let page = mmu.alloc_for(stack_perm);
+            mmu.map(page, stack_vpn, stack_perm);
+            self.mapped.insert(stack_vpn, stack_perm);
+        };
+        if self.map_descending {
+            vpns.rev().for_each(&mut map);
+        } else {
+            vpns.for_each(&mut map);
+        }
+    }
+
+    /// Whether any page is mapped both writable and executable
+    fn has_wx(&self) -> bool {
+        self.mapped.values().any(|perm| perm.w && perm.x)
+    }
+
+    /// End of the highest writable page, or of the highest page if none is writable
+    fn data_end(&self) -> usize {
+        let last = self.mapped.iter().rev().find(|(_, perm)| perm.w).or(self.mapped.iter().next_back());
+        last.map_or(0, |(vpn, _)| VirtAddr::from(VirtPageNum(vpn + 1)).0)
+    }
+
+    /// Allocate the frame backing `vpn`, to be mapped with `perm`
+    fn alloc<M: MMU>(&self, mmu: &mut M, vpn: usize, perm: Perm) -> M::AllocatedPage {
+        if self.dma.iter().any(|range| range.contains(&vpn)) {
+            mmu.alloc_aligned(self.dma_align)
+        } else {
+            mmu.alloc_for(perm)
+        }
+    }
+
+    /**
+     * Find the first `pages` free pages above the image, skipping `occupied` vpn ranges and
+     * keeping `gap` free pages around the image and each of them.
+     * Returns the first vpn of the found range.
+     */
+    fn place_above(&self, pages: usize, occupied: &[Range<usize>], gap: usize) -> usize {
+        self.place_aligned(pages, 1, occupied, gap)
+    }
+
+    /// Same as `place_above`, with the returned vpn a multiple of `align` pages
+    fn place_aligned(&self, pages: usize, align: usize, occupied: &[Range<usize>], gap: usize) -> usize {
+        let mut vpn = self.mapped.keys().next_back().map_or(0, |last| last + 1 + gap).next_multiple_of(align);
+        while let Some(overlap) = occupied.iter().find(|range| range.start < vpn + pages + gap && vpn < range.end + gap) {
+            vpn = (overlap.end + gap).next_multiple_of(align);
+        }
+        vpn
+    }
+
+    /**
+     * Map `vaddr` with `perm`, filling it with `content` followed by zeros.
+     * A page already mapped by a previous region is shared, and its permission is merged.
+     */
+    fn map_region<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M, vaddr: Range<usize>, perm: Perm, content: &[u8]) {
+        self.fill_pages(mmu, vaddr.clone(), perm, content.len(), &mut |offset, dst| {
+            dst.copy_from_slice(&content[offset .. offset + dst.len()]);
+        });
+
+        // Computed from the source instead of the written pages, to avoid another pass
+        let crc = (self.segment_crc && !perm.w).then(|| {
+            let mut crc = Crc32::default();
+            crc.update(content);
+            crc.update_zeros(vaddr.len() - content.len());
+            crc.finish()
+        });
+        self.regions.push(Region { vaddr, perm, crc, file_offset: 0, file_size: 0 });
+    }
+
+    /**
+     * Same as `map_region`, with `content_len` bytes of content produced by `fill` directly into
+     * the mapped pages, from `file_offset` in the ELF file. `fill` receives the offset into the
+     * content and the destination slice.
+     */
+    fn map_region_with<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M, vaddr: Range<usize>, perm: Perm, file_offset: usize, content_len: usize, fill: &mut dyn FnMut(usize, &mut [u8])) {
+        self.fill_pages(mmu, vaddr.clone(), perm, content_len, fill);
+        let crc = (self.segment_crc && !perm.w).then(|| {
+            let mut crc = Crc32::default();
+            for vpn in VirtAddr(vaddr.start).floor().number() .. VirtAddr(vaddr.end).ceil().number() {
+                let page_start = VirtAddr::from(VirtPageNum(vpn)).0;
+                let range = vaddr.start.max(page_start) .. vaddr.end.min(page_start + PAGE_SIZE);
+                crc.update(self.frame_bytes(range));
+            }
+            crc.finish()
+        });
+        self.regions.push(Region { vaddr, perm, crc, file_offset, file_size: content_len });
+    }
+
+    /**
+     * Add a page to the image with `perm`, merged with the permission of previous regions sharing
+     * it. Returns whether a fresh frame was allocated for it.
+     */
+    fn stage_page<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M, vpn: usize, perm: Perm) -> bool {
+        match self.mapped.get_mut(&vpn) {
+            Some(existing) => {
+                *existing = existing.union(perm);
+                false
+            },
+            None => {
+                let page = self.alloc(mmu, vpn, perm);
+                self.frames.insert(vpn, page);
+                self.mapped.insert(vpn, perm);
+                true
+            },
+        }
+    }
+
+    /**
+     * Try to back the pages of `vpns` not shared with a previous region with contiguous zeroed
+     * frames, so that they are mapped at once. DMA pages keep their own aligned frames.
+     */
+    fn stage_contiguous<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M, vpns: Range<usize>, perm: Perm) {
+        let fresh = (vpns.start + self.mapped.contains_key(&vpns.start) as usize) .. vpns.end;
+        if fresh.len() < 2 || fresh.clone().any(|vpn| self.mapped.contains_key(&vpn) || self.dma.iter().any(|range| range.contains(&vpn))) {
+            return;
+        }
+        let Some(pages) = mmu.alloc_contiguous(fresh.len()) else { return };
+        for (vpn, page) in fresh.clone().zip(pages) {
+            self.frames.insert(vpn, page);
+            self.mapped.insert(vpn, perm);
+            let page_start = VirtAddr::from(VirtPageNum(vpn)).0;
+            self.frame_bytes(page_start .. page_start + PAGE_SIZE).fill(0);
+        }
+        self.contiguous.push(fresh);
+    }
+
+    /// Content of a not yet mapped frame, for a vaddr range within a single page
+    #[allow(clippy::mut_from_ref)]
+    fn frame_bytes(&self, vaddr: Range<usize>) -> &mut [u8] {
+        let page = self.frames[&VirtAddr(vaddr.start).floor().number()];
+        let start = page.inner().as_ptr() as usize + VirtAddr(vaddr.start).page_offset();
+        unsafe { core::slice::from_raw_parts_mut(start as *mut u8, vaddr.len()) }
+    }
+
+    /**
+     * Stage the pages of a region, and fill them with its content followed by zeros. A page shared
+     * with a previous region, e.g. the `.data`/`.bss` boundary page, keeps the bytes below this
+     * region: only the region's own part of it is copied, then zeroed up to the region end.
+     * Fresh pages are zeroed as a whole first, so bytes past the end of a region are zero.
+     */
+    fn fill_pages<M: MMU<AllocatedPage = P>>(&mut self, mmu: &mut M, vaddr: Range<usize>, perm: Perm, content_len: usize, fill: &mut dyn FnMut(usize, &mut [u8])) {
+        let content_end = vaddr.start + content_len;
+        let vpns = VirtAddr(vaddr.start).floor().number() .. VirtAddr(vaddr.end).ceil().number();
+        self.stage_contiguous(mmu, vpns.clone(), perm);
+        for vpn in vpns {
+            let page_start = VirtAddr::from(VirtPageNum(vpn)).0;
+            let page_end = page_start + PAGE_SIZE;
+
+            if self.stage_page(mmu, vpn, perm) {
+                self.frame_bytes(page_start .. page_end).fill(0);
+            } else {
+                // Shared with the previous region, only touch our own part of the page
+                let zero_start = content_end.max(page_start);
+                let zero_end = vaddr.end.min(page_end);
+                if zero_start < zero_end {
+                    self.frame_bytes(zero_start .. zero_end).fill(0);
+                }
+            }
+
+            let copy_start = vaddr.start.max(page_start);
+            let copy_end = content_end.min(page_end);
+            if copy_start < copy_end {
+                fill(copy_start - vaddr.start, self.frame_bytes(copy_start .. copy_end));
+            }
+        }
+    }
+}
+
+/**
+ * Vpns spanned by the PT_LOAD segments of an ELF, at its link-time addresses
+ */
+fn segment_pages(buf: &[u8]) -> Result<Range<usize>, LoadError> {
+    let header = Header::parse(buf).ok_or(LoadError::InvalidElf)?;
+    let segments = || header.phdrs.iter().filter(|ph| ph.ty == ProgramType::LOAD);
+    let lowest = segments().map(|ph| VirtAddr(ph.vaddr).floor().number()).min().ok_or(LoadError::InvalidElf)?;
+    let highest = segments()
+        .map(|ph| ph.mem_range().map(|range| VirtAddr(range.end).ceil().number()))
+        .collect::<Option<Vec<_>>>()
+        .and_then(|ends| ends.into_iter().max())
+        .ok_or(LoadError::InvalidElf)?;
+    Ok(lowest .. highest)
+}
+
+/**
+ * Link-time vaddrs of the GOT slots of an ELF, from its `.got` and `.got.plt` sections, leaving
+ * out the `.got.plt` header the dynamic linker fills in at run time
+ */
+fn got_slots(buf: &[u8]) -> Vec<Range<usize>> {
+    if !has_section_headers(buf) {
+        return Vec::new();
+    }
+    let Ok(parsed) = elf_rs::Elf64::from_bytes(buf) else { return Vec::new() };
+    parsed.section_header_iter()
+        .filter_map(|sec_hdr| {
+            let reserved = match sec_hdr.section_name() {
+                b".got" => 0,
+                b".got.plt" => GOT_PLT_RESERVED * core::mem::size_of::<usize>(),
+                _ => return None,
+            };
+            let start = sec_hdr.addr() as usize;
+            Some((start + reserved).min(start + sec_hdr.size() as usize) .. start + sec_hdr.size() as usize)
+        })
+        .collect()
+}
+
+/**
+ * Whether the GOT slot at link-time vaddr `offset` may be left zero by relocation: no dynamic
+ * relocation targets it, so the static linker already wrote its final value, or one targets it
+ * against an undefined weak symbol
+ */
+fn got_slot_may_be_zero(dynamic: Option<&Dynamic>, offset: usize) -> bool {
+    let Some(dynamic) = dynamic else { return true };
+    let mut relocations = dynamic.relas().filter(|ent| ent.offset == offset).peekable();
+    relocations.peek().is_none() || relocations.any(|ent| {
+        dynamic.resolve_sym(ent.sym_index()).is_some_and(|(sym, _)| sym.shndx == SHN_UNDEF && sym.binding() == Binding::Weak)
+    })
+}
+
+/**
+ * Group fixups sorted by target vaddr into the range of indices targeting each page
+ */
+fn index_by_page(fixups: &[Fixup]) -> BTreeMap<VirtPageNum, Range<usize>> {
+    let mut index: BTreeMap<VirtPageNum, Range<usize>> = BTreeMap::new();
+    for (idx, fixup) in fixups.iter().enumerate() {
+        index.entry(VirtAddr(fixup.vaddr).floor())
+            .and_modify(|range| range.end = idx + 1)
+            .or_insert(idx .. idx + 1);
+    }
+    index
+}
+
+/**
+ * Read bytes from the process address space, through the MMU's translation
+ */
+fn read_bytes<M: MMU>(mmu: &M, vaddr: usize, dst: &mut [u8]) {
+    let mut read = 0;
+    while read < dst.len() {
+        let cur = vaddr + read;
+        let len = (PAGE_SIZE - VirtAddr(cur).page_offset()).min(dst.len() - read);
+        let paddr = mmu.translate(cur).unwrap();
+        unsafe { core::ptr::copy_nonoverlapping(paddr as *const u8, dst[read..].as_mut_ptr(), len) };
+        read += len;
+    }
+}
+
+/**
+ * Compute the CRC32 of a range of the process address space, through the MMU's translation
+ */
+fn crc_bytes<M: MMU>(mmu: &M, range: Range<usize>) -> u32 {
+    let mut crc = Crc32::default();
+    let mut cur = range.start;
+    while cur < range.end {
+        let len = (PAGE_SIZE - VirtAddr(cur).page_offset()).min(range.end - cur);
+        let paddr = mmu.translate(cur).unwrap();
+        crc.update(unsafe { core::slice::from_raw_parts(paddr as *const u8, len) });
+        cur += len;
+    }
+    crc.finish()
+}
+
+/**
+ * Permission of each page of the VDSO image at paddr `start .. end`, taken from its PT_LOAD
+ * segments: text is mapped `r-x`, everything else `r--`. A VDSO that isn't a valid ELF is
+ * mapped `r-x` as a whole.
+ */
+fn vdso_page_perms(start: usize, end: usize) -> Vec<Perm> {
+    let pages = PhysAddr(end).ceil().0 - PhysAddr(start).floor().0;
+    let text = Perm { r: true, w: false, x: true };
+
+    // The VDSO lives in identity-mapped physical memory
+    let blob = unsafe { core::slice::from_raw_parts(start as *const u8, end - start) };
+    let header = match Header::parse(blob).filter(|_| blob.starts_with(b"\x7fELF")) {
+        Some(header) => header,
+        None => return vec![text; pages],
+    };
+
+    let mut perms = vec![Perm { r: true, w: false, x: false }; pages];
+    for ph in header.phdrs.iter().filter(|ph| ph.ty == ProgramType::LOAD && ph.flags.contains(ProgramHeaderFlags::EXECUTE)) {
+        let first = ph.offset / PAGE_SIZE;
+        let last = ph.offset.saturating_add(ph.filesz).div_ceil(PAGE_SIZE).min(pages);
+        for perm in perms.get_mut(first .. last).into_iter().flatten() {
+            *perm = text;
+        }
+    }
+    perms
+}
+
+/**
+ * Pick the highest page table level usable for mapping `count` pages starting at `ppn` onto `vpn`
+ */
+fn mapping_level(ppn: usize, vpn: usize, count: usize) -> usize {
+    (0 .. PT_LEVELS).rev()
+        .find(|level| {
+            let pages = level_pages(*level);
+            ppn.is_multiple_of(pages) && VirtPageNum(vpn).aligned_at_level(*level) && count >= pages
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests;
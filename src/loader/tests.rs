@@ -0,0 +1,1882 @@
+use super::*;
+use crate::elf::{DF_1_PIE, EF_RISCV_RVC, R_RISCV_32, R_RISCV_64, R_RISCV_JUMP_SLOT, R_RISCV_RELATIVE, R_RISCV_SET8, R_RISCV_SET16, R_RISCV_TLS_DTPMOD64, R_RISCV_TLS_DTPREL64, R_RISCV_TLS_TPREL64, SHN_ABS, TLS_DTV_OFFSET};
+use crate::testing::*;
+use crate::testing::{PT_GNU_RELRO, PT_TLS};
+
+const HUGE: usize = 0x200000;
+
+fn no_lookup(_: &[u8]) -> Option<usize> {
+    None
+}
+
+type Lookup = fn(&[u8]) -> Option<usize>;
+
+fn no_vdso() -> Option<VDSOConfig<Lookup>> {
+    None
+}
+
+fn empty_stack() -> StackConfig {
+    StackConfig::fixed(0, 0)
+}
+
+#[test]
+fn mapping_level_picks_largest_aligned_level() {
+    assert_eq!(mapping_level(0x200, 0x400, 512), 1);
+    assert_eq!(mapping_level(0x200, 0x400, 511), 0);
+    assert_eq!(mapping_level(0x201, 0x400, 1024), 0);
+    assert_eq!(mapping_level(0x40000, 0x80000, 0x40000), 2);
+}
+
+#[test]
+fn vdso_uses_huge_leaf_when_aligned() {
+    let mut mmu = MockMmu::new();
+    let ppn = mmu.reserve(512, 512);
+    let start = ppn * 4096;
+    let config = VDSOConfig { start, end: start + HUGE, target: Some(0x4000_0000), lookup: no_lookup };
+    Loader::load(&ElfBuilder::new(ET_DYN).build(), &mut mmu, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(mmu.calls, [Call::MapAtLevel { ppn, vpn: 0x40000, perm: RX, level: 1 }]);
+    assert_eq!(mmu.translate(0x4000_0000 + HUGE - 1), Some(start + HUGE - 1));
+}
+
+#[test]
+fn vdso_tail_falls_back_to_small_pages() {
+    let mut mmu = MockMmu::new();
+    let ppn = mmu.reserve(514, 512);
+    let start = ppn * 4096;
+    let config = VDSOConfig { start, end: start + HUGE + 0x2000, target: Some(0x4000_0000), lookup: no_lookup };
+    Loader::load(&ElfBuilder::new(ET_DYN).build(), &mut mmu, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(mmu.calls, [
+        Call::MapAtLevel { ppn, vpn: 0x40000, perm: RX, level: 1 },
+        Call::MapAtLevel { ppn: ppn + 512, vpn: 0x40200, perm: RX, level: 0 },
+        Call::MapAtLevel { ppn: ppn + 513, vpn: 0x40201, perm: RX, level: 0 },
+    ]);
+}
+
+/// An MMU relying on every default method of the trait
+struct Plain(MockMmu);
+
+impl MMU for Plain {
+    type AllocatedPage = <MockMmu as MMU>::AllocatedPage;
+
+    fn alloc(&mut self) -> Self::AllocatedPage {
+        self.0.alloc()
+    }
+
+    fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        self.0.map_existing(ppn, vpn, perm)
+    }
+
+    fn translate(&self, vaddr: usize) -> Option<usize> {
+        self.0.translate(vaddr)
+    }
+}
+
+#[test]
+fn default_map_at_level_maps_each_page() {
+    let mut mmu = Plain(MockMmu::new());
+    mmu.map_at_level(0x200, 0x400, RX, 1);
+    assert_eq!(mmu.0.mapped_vpns(), (0x400 .. 0x600).collect::<alloc::vec::Vec<_>>());
+    assert_eq!(mmu.0.table[&0x5ff], (0x3ff, RX));
+}
+
+#[test]
+fn vdso_slots_found_through_pt_dynamic() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x3000, 8);
+    let sym = builder.sym(b"vdso_fn", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, 5, sym, 0);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"vdso_fn").then_some(start + 0x10);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+    Loader::load(&buf, &mut mmu, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(mmu.perm(0x3000), Some(RW));
+    assert_eq!(mmu.word(0x3000), 0x4000_0010);
+}
+
+/// An image with a RELATIVE relocation to `value` in the `.got` slot at 0x3000
+fn relative_image(value: usize) -> alloc::vec::Vec<u8> {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x3000, 8);
+    builder.rela(0x3000, 3, 0, value);
+    builder.build()
+}
+
+#[test]
+fn lazy_relocation_defers_until_relocate_page() {
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load(&relative_image(0x1234), &mut mmu, no_vdso(), empty_stack(), options).unwrap();
+
+    assert_eq!(mmu.perm(0x3000), Some(NO_ACCESS));
+    assert_eq!(mmu.word(0x3000), 0);
+    assert!(!loader.relocate_page(&mut mmu, 0x5000));
+
+    assert!(loader.relocate_page(&mut mmu, 0x3008));
+    assert_eq!(mmu.perm(0x3000), Some(RW));
+    assert_eq!(mmu.word(0x3000), 0x1234);
+}
+
+#[test]
+fn eager_relocation_applies_at_load() {
+    let mut mmu = MockMmu::new();
+    let mut loader = Loader::load(&relative_image(0x1234), &mut mmu, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(mmu.perm(0x3000), Some(RW));
+    assert_eq!(mmu.word(0x3000), 0x1234);
+    assert!(!loader.relocate_page(&mut mmu, 0x3000));
+}
+
+#[test]
+fn relocate_page_applies_once() {
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load(&relative_image(0x1234), &mut mmu, no_vdso(), empty_stack(), options).unwrap();
+
+    assert!(loader.relocate_page(&mut mmu, 0x3000));
+    // The program overwrites the relocated slot, which must not be relocated again
+    mmu.write(0x3000, &5usize.to_le_bytes());
+    let calls = mmu.calls.len();
+    assert!(!loader.relocate_page(&mut mmu, 0x3000));
+    assert_eq!(mmu.word(0x3000), 5);
+    assert_eq!(mmu.calls.len(), calls);
+}
+
+#[test]
+fn load_segments_copies_content_and_zero_fills() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.entry = 0x1000;
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    // Data sharing its last page with the zero-filled part
+    builder.load(0x2ff8, PF_R | PF_W, &[0xaa; 8], 0x1010);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    // Page contents left over from a previous user must not leak
+    mmu.fill_free(0xff);
+    let loader = Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(loader.entry, 0x10_1000);
+    assert_eq!(loader.regions.iter().map(|region| region.vaddr.clone()).collect::<alloc::vec::Vec<_>>(), [0x10_1000 .. 0x10_1004, 0x10_2ff8 .. 0x10_4008]);
+    assert_eq!(mmu.read(0x10_1000, 5), b"text\0");
+    assert_eq!(mmu.perm(0x10_1000), Some(RX));
+    assert_eq!(mmu.read(0x10_2ff8, 8), [0xaa; 8]);
+    assert_eq!(mmu.word(0x10_3000), 0);
+    assert_eq!(mmu.perm(0x10_4000), Some(RW));
+    assert_eq!(mmu.perm(0x10_5000), None);
+}
+
+#[test]
+fn load_segments_merges_shared_page_permissions() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x1800, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.perm(0x1000), Some(RWX));
+    assert_eq!(mmu.read(0x1000, 4), b"text");
+    assert_eq!(mmu.read(0x1800, 4), b"data");
+}
+
+#[test]
+fn load_segments_rejects_filesz_above_memsz() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R, &[0; 16], 8);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let result = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::BadSegmentSizes));
+    assert!(mmu.calls.is_empty());
+}
+
+#[test]
+fn load_rejects_truncated_file() {
+    let mut mmu = MockMmu::new();
+    let result = Loader::load(&[0x7f, b'E', b'L', b'F'], &mut mmu, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::InvalidElf));
+}
+
+#[test]
+fn vdso_placed_above_image_and_stack() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 8], 8);
+    let sym = builder.sym(b"vdso_fn", 0, STB_GLOBAL, 0);
+    builder.rela(0x1000, 5, sym, 0);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"vdso_fn").then_some(start + 0x100);
+    let config = VDSOConfig { start, end: start + 0x1000, target: None, lookup };
+    // The dynamic tables take the page at 0x2000
+    let stack = StackConfig::fixed(0x3000, 0x5000);
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), stack, LoadOptions::default()).unwrap();
+
+    assert_eq!(loader.vdso_base, Some(0x5000));
+    assert_eq!(mmu.translate(0x5000), Some(start));
+    assert_eq!(mmu.perm(0x5000), Some(RX));
+    assert_eq!(mmu.word(0x1000), 0x5100);
+}
+
+#[test]
+fn verify_integrity_detects_modified_text() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 0x10);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { segment_crc: true, ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert!(loader.regions[0].crc.is_some());
+    assert!(loader.regions[1].crc.is_none());
+    assert!(loader.verify_integrity(&mmu));
+
+    // Writable data isn't covered
+    mmu.write(0x2000, b"DATA");
+    assert!(loader.verify_integrity(&mmu));
+    // Including the zero-filled tail
+    mmu.write(0x100f, b"!");
+    assert!(!loader.verify_integrity(&mmu));
+}
+
+#[test]
+fn lenient_option_accepts_bad_header_size() {
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let mut buf = builder.build();
+    buf[52 .. 54].copy_from_slice(&0xffffu16.to_le_bytes());
+
+    let mut mmu = MockMmu::new();
+    let result = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::InvalidElf));
+
+    let options = LoadOptions { lenient: true, ..LoadOptions::default() };
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.read(0x1000, 4), b"text");
+}
+
+#[test]
+fn bias_for_base_moves_lowest_segment() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+    assert_eq!(Loader::bias_for_base(&buf, 0x40_0000), Ok(0x3f_f000));
+    assert_eq!(Loader::bias_for_base(&ElfBuilder::new(ET_DYN).build(), 0x40_0000), Err(LoadError::InvalidElf));
+    assert_eq!(Loader::bias_for_base(&buf, 0x1000), Ok(0));
+    assert_eq!(Loader::bias_for_base(&buf, 0x800), Err(LoadError::NoSpace));
+}
+
+#[test]
+fn tls_template_zero_fills_tbss() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"tdata", 5);
+    builder.phdr(PT_TLS, PF_R, 0x1000, 5, 0x10);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let tls = loader.tls.unwrap();
+    assert_eq!(tls.block_size(), 0x10);
+    let mut block = [0xff; 0x18];
+    tls.init_block(&mut block);
+    assert_eq!(&block[.. 0x10], b"tdata\0\0\0\0\0\0\0\0\0\0\0");
+    assert_eq!(block[0x10], 0xff);
+}
+
+#[test]
+fn tls_rejects_filesz_above_memsz() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"tdata", 5);
+    builder.phdr(PT_TLS, PF_R, 0x1000, 5, 4);
+    let buf = builder.build();
+
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::BadSegmentSizes));
+}
+
+#[test]
+fn vdso_placement_skips_reserved_ranges() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(2, 1) * 4096;
+    let config = VDSOConfig { start, end: start + 0x2000, target: None, lookup: no_lookup };
+    // The first range only leaves a single page free before the second
+    let options = LoadOptions { reserved_ranges: &[(0x2000, 0x3800), (0x5000, 0x6000)], ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), empty_stack(), options).unwrap();
+    assert_eq!(loader.vdso_base, Some(0x6000));
+}
+
+#[test]
+fn load_segments_checks_congruence() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4).vaddr = 0x1008;
+    let buf = builder.build();
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::BadSegmentAlignment));
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    let text = builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    text.vaddr = 0x1008;
+    text.align = 1;
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x1008, 4), b"text");
+}
+
+#[test]
+fn check_relocations_reports_issues() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x20], 0x20);
+    let known = builder.sym(b"known", 0, STB_GLOBAL, 0);
+    let missing = builder.sym(b"missing", 0, STB_GLOBAL, 0);
+    let weak = builder.sym(b"weak", 0, STB_WEAK, 0);
+    builder.rela(0x1000, R_RISCV_64, known, 0);
+    builder.rela(0x1008, R_RISCV_64, missing, 0);
+    builder.rela(0x1010, R_RISCV_JUMP_SLOT, weak, 0);
+    builder.rela(0x1018, 4, 0, 0);
+    builder.rela(0x8000, R_RISCV_RELATIVE, 0, 0);
+    let buf = builder.build();
+
+    let issues = Loader::check_relocations(&buf, |name| (name == b"known").then_some(0x5000)).unwrap();
+    assert_eq!(issues, [
+        RelocIssue::Unresolved { offset: 0x1008, name: b"missing".to_vec() },
+        RelocIssue::Unsupported { offset: 0x1018, ty: 4 },
+        RelocIssue::OutOfRange { offset: 0x8000 },
+    ]);
+}
+
+#[test]
+fn dynamic_relocations_use_bias_and_local_symbols() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x10], 0x10);
+    let local = builder.sym(b"local", 0x1234, STB_GLOBAL, SHN_TEXT);
+    builder.rela(0x1000, R_RISCV_64, local, 8);
+    builder.rela(0x1008, R_RISCV_RELATIVE, 0, 0x1000);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x10_1000), 0x10_123c);
+    assert_eq!(mmu.word(0x10_1008), 0x10_1000);
+}
+
+#[test]
+fn relocation_outside_image_fails() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x10], 0x10);
+    builder.rela(0x8000, R_RISCV_RELATIVE, 0, 0);
+    let buf = builder.build();
+
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::RelocOutOfRange));
+}
+
+/// An image whose GOT at 0x1000, named by DT_PLTGOT, holds the link-time `_DYNAMIC`
+fn got0_image() -> (alloc::vec::Vec<u8>, usize) {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x18], 0x18);
+    builder.dyn_entries.push((DT_PLTGOT, 0x1000));
+    let mut buf = builder.build();
+    let dynamic_vaddr = Header::parse(&buf).unwrap().dynamic_vaddr().unwrap();
+    buf[0x1000 .. 0x1008].copy_from_slice(&dynamic_vaddr.to_le_bytes());
+    (buf, dynamic_vaddr)
+}
+
+#[test]
+fn got0_points_at_relocated_dynamic() {
+    let (buf, dynamic_vaddr) = got0_image();
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x10_1000), 0x10_0000 + dynamic_vaddr);
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { preserve_got0: true, ..LoadOptions::default() };
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x10_1000), dynamic_vaddr);
+}
+
+#[test]
+fn got0_targets_got_section_over_got_plt() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    // .got at 0x1000, .got.plt at 0x1010 with its resolver placeholder
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x20], 0x20);
+    builder.write(0x1010, &usize::MAX.to_le_bytes());
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1000, 0x10);
+    builder.section(b".got.plt", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1010, 0x10);
+    builder.dyn_entries.push((DT_PLTGOT, 0x1010));
+    let mut buf = builder.build();
+    let dynamic_vaddr = Header::parse(&buf).unwrap().dynamic_vaddr().unwrap();
+    buf[0x1000 .. 0x1008].copy_from_slice(&dynamic_vaddr.to_le_bytes());
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x10_1000), 0x10_0000 + dynamic_vaddr);
+    assert_eq!(mmu.word(0x10_1010), usize::MAX);
+}
+
+#[test]
+fn got0_left_alone_unless_holding_dynamic() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &usize::MAX.to_le_bytes(), 8);
+    builder.dyn_entries.push((DT_PLTGOT, 0x1000));
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x10_1000), usize::MAX);
+}
+
+#[test]
+fn symbol_vaddr_finds_biased_exports() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.sym(b"main", 0x1000, STB_GLOBAL, SHN_TEXT);
+    builder.sym(b"hidden", 0x1002, STB_LOCAL, SHN_TEXT);
+    let buf = builder.build();
+
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.symbol_vaddr(b"main"), Some(0x10_1000));
+    assert_eq!(loader.symbol_vaddr(b"hidden"), None);
+    assert_eq!(loader.symbol_vaddr(b"missing"), None);
+}
+
+#[test]
+fn dma_sections_get_aligned_frames() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x1100], 0x1100);
+    builder.section(b".dma", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x2000, 0x100);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { dma_sections: &[b".dma"], dma_align: 0x10000, ..LoadOptions::default() };
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    let ppn = mmu.ppn(0x2000).unwrap();
+    assert!(mmu.calls.contains(&Call::AllocAligned { ppn, align: 0x10000 }));
+    assert_eq!(ppn % 16, 0);
+    assert!(mmu.calls.contains(&Call::AllocFor { ppn: mmu.ppn(0x1000).unwrap(), perm: RW }));
+}
+
+#[test]
+fn default_alloc_aligned_allocates() {
+    let mut mmu = Plain(MockMmu::new());
+    mmu.alloc_aligned(0x10000);
+    assert!(matches!(mmu.0.calls[..], [Call::Alloc { .. }]));
+}
+
+#[test]
+fn random_bias_stays_within_range() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, &[0; 0x1800], 0x1800);
+    builder.section_headers = false;
+    let buf = builder.build();
+
+    assert_eq!(Loader::random_bias(&buf, 0x10_0000 .. 0x10_4000, &mut || 0), Ok(0xff000));
+    assert_eq!(Loader::random_bias(&buf, 0x10_0000 .. 0x10_4000, &mut || 5), Ok(0x10_1000));
+    // Unaligned bounds shrink the range to whole pages
+    assert_eq!(Loader::random_bias(&buf, 0x10_0001 .. 0x10_2fff, &mut || 0), Err(LoadError::NoSpace));
+}
+
+#[test]
+fn entropy_slides_vdso() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let config = VDSOConfig { start, end: start + 0x1000, target: None, lookup: no_lookup };
+    let mut entropy = || VDSO_SLIDE_PAGES as u64 + 3;
+    let options = LoadOptions { entropy: Some(&mut entropy), ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), empty_stack(), options).unwrap();
+    assert_eq!(loader.vdso_base, Some(0x5000));
+}
+
+/// A loaded image at `bias` exporting `syms` as `(name, vaddr, bind)`
+fn exporting(syms: &[(&[u8], usize, u8)], bias: usize) -> Loader {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    for (name, vaddr, bind) in syms {
+        builder.sym(name, *vaddr, *bind, SHN_TEXT);
+    }
+    let buf = builder.build();
+    Loader::load_segments(&buf, &mut MockMmu::new(), bias, no_vdso(), empty_stack(), LoadOptions::default()).unwrap()
+}
+
+#[test]
+fn resolve_in_order_picks_first_definition() {
+    let interposer = exporting(&[(b"malloc", 0x1000, STB_WEAK)], 0x10_0000);
+    let libc = exporting(&[(b"malloc", 0x1000, STB_GLOBAL), (b"free", 0x1004, STB_GLOBAL)], 0x20_0000);
+    let objects = [&interposer, &libc];
+    let resolve = resolve_in_order(&objects);
+    assert_eq!(resolve(b"malloc"), Some(0x10_1000));
+    assert_eq!(resolve(b"free"), Some(0x20_1004));
+    assert_eq!(resolve(b"calloc"), None);
+}
+
+#[test]
+fn plan_coalesces_contiguous_pages() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 0x2000);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let stack = StackConfig::fixed(0x10_0000, 0x10_2000);
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), stack, LoadOptions::default()).unwrap();
+    let ppn = mmu.ppn(0x1000).unwrap();
+    assert_eq!(loader.plan(&mmu), [
+        LayoutEntry { vaddr: 0x1000 .. 0x2000, ppn: ppn .. ppn + 1, perm: RX },
+        LayoutEntry { vaddr: 0x2000 .. 0x4000, ppn: ppn + 1 .. ppn + 3, perm: RW },
+        LayoutEntry { vaddr: 0x10_0000 .. 0x10_2000, ppn: ppn + 3 .. ppn + 5, perm: RW },
+    ]);
+}
+
+#[test]
+fn tls_relocations_patch_template() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x10], 0x10);
+    builder.phdr(PT_TLS, PF_R, 0x1000, 0x10, 0x20);
+    builder.rela(0x1008, R_RISCV_RELATIVE, 0, 0x1234);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let tls = loader.tls.unwrap();
+    assert_eq!(tls.image[8 .. 16], 0x10_1234usize.to_le_bytes());
+    // The mapped copy is left alone
+    assert_eq!(mmu.word(0x10_1008), 0);
+}
+
+#[test]
+fn data_end_follows_highest_writable_page() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"data", 0x1800);
+    builder.load(0x4000, PF_R, b"rodata", 6);
+    let buf = builder.build();
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.data_end, 0x3000);
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.data_end, 0x2000);
+}
+
+#[test]
+fn stack_placed_above_vdso_with_gaps() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(2, 1) * 4096;
+    let config = VDSOConfig { start, end: start + 0x2000, target: None, lookup: no_lookup };
+    let options = LoadOptions { min_gap_pages: 1, ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), StackConfig::above_image(0x1800), options).unwrap();
+
+    assert_eq!(loader.vdso_base, Some(0x3000));
+    assert_eq!(loader.stack, 0x6000 .. 0x8000);
+    assert_eq!(mmu.perm(0x6000), Some(RW));
+    assert_eq!(mmu.perm(0x5000), None);
+    assert_eq!(mmu.perm(0x2000), None);
+}
+
+#[test]
+fn load_segments_fetched_reads_dynamic_back() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"data\0\0\0\0\0\0\0\0\0\0\0\0", 0x10);
+    builder.rela(0x1008, R_RISCV_RELATIVE, 0, 0x1000);
+    let buf = builder.build();
+
+    let mut fetched = alloc::vec::Vec::new();
+    let mut fetch = |offset: usize, dst: &mut [u8]| {
+        fetched.push(offset .. offset + dst.len());
+        dst.copy_from_slice(&buf[offset .. offset + dst.len()]);
+    };
+    let mut mmu = MockMmu::new();
+    Loader::load_segments_fetched(&buf[.. 0x1000], &mut fetch, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x10_1000, 4), b"data");
+    assert_eq!(mmu.word(0x10_1008), 0x10_1000);
+    assert_eq!(fetched[0], 0x1000 .. 0x1010);
+}
+
+#[test]
+fn misaligned_vdso_ranges_rejected() {
+    let buf = relative_image(0);
+    let start = MockMmu::new().reserve(1, 1) * 4096;
+    for (start, end, target) in [(start + 0x10, start + 0x1000, None), (start, start, None), (start, start + 0x1000, Some(0x4000_0010))] {
+        let config = VDSOConfig { start, end, target, lookup: no_lookup };
+        let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, Some(config), empty_stack(), LoadOptions::default());
+        assert_eq!(result.err(), Some(LoadError::BadVDSORange));
+    }
+}
+
+#[test]
+fn vdso_data_pages_mapped_read_only() {
+    let mut vdso = ElfBuilder::new(ET_DYN);
+    vdso.load(0x1000, PF_R | PF_X, b"text", 4);
+    vdso.load(0x2000, PF_R | PF_W, b"data", 4);
+    let vdso = vdso.build();
+
+    let mut mmu = MockMmu::new();
+    let range = mmu.physical(&vdso, 1);
+    let config = VDSOConfig { start: range.start, end: range.end, target: Some(0x4000_0000), lookup: no_lookup };
+    Loader::load_segments(&relative_image(0), &mut mmu, 0, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.perm(0x4000_0000), Some(R));
+    assert_eq!(mmu.perm(0x4000_1000), Some(RX));
+    assert_eq!(mmu.perm(0x4000_2000), Some(R));
+    assert_eq!(mmu.translate(0x4000_1000), Some(range.start + 0x1000));
+}
+
+#[test]
+fn check_prevalues_accepts_zero_or_addend() {
+    for (prevalue, ok) in [(0usize, true), (0x1234, true), (0x5678, false)] {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x3000, PF_R | PF_W, &prevalue.to_le_bytes(), 8);
+        builder.rela(0x3000, 3, 0, 0x1234);
+        let buf = builder.build();
+
+        let options = LoadOptions { check_prevalues: true, ..LoadOptions::default() };
+        let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), options);
+        assert_eq!(result.err(), (!ok).then_some(LoadError::UnexpectedPrevalue { vaddr: 0x10_3000 }));
+    }
+}
+
+#[test]
+fn restore_maps_layout_and_missing_stack_pages() {
+    let mut mmu = MockMmu::new();
+    mmu.fill_free(0xaa);
+    let layout: [(Range<usize>, Perm, &[u8]); 2] = [
+        (0x1000 .. 0x2000, RX, b"text"),
+        (0x8000 .. 0xa000, RW, b"saved stack"),
+    ];
+    let stack = StackConfig::fixed(0x8000, 0xb000);
+    let loader = Loader::restore(&layout, &mut mmu, stack, None).unwrap();
+
+    assert_eq!(mmu.read(0x1000, 6), b"text\0\0");
+    assert_eq!(mmu.perm(0x1000), Some(RX));
+    assert_eq!(mmu.read(0x8000, 11), b"saved stack");
+    assert_eq!(mmu.read(0x9000, 4), [0; 4]);
+    assert_eq!(mmu.perm(0xa000), Some(RW));
+    assert_eq!(loader.stack, 0x8000 .. 0xb000);
+    assert_eq!(mmu.mapped_vpns(), [1, 8, 9, 10]);
+
+    let oversized: [(Range<usize>, Perm, &[u8]); 1] = [(0x1000 .. 0x1002, R, b"text")];
+    assert_eq!(Loader::restore(&oversized, &mut mmu, empty_stack(), None).err(), Some(LoadError::BadSegmentSizes));
+}
+
+#[test]
+fn load_plan_covers_merged_pages() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, &[0; 0x10], 0x10);
+    builder.load(0x1800, PF_R | PF_W, &[0; 0x10], 0x1000);
+    let buf = builder.build();
+
+    let plan = LoadPlan::new(&buf, 0x10_0000).unwrap();
+    assert_eq!(plan.covers(0x10_1000), Some(RWX));
+    assert_eq!(plan.covers(0x10_2fff), Some(RW));
+    assert_eq!(plan.covers(0x10_3000), None);
+    assert_eq!(plan.covers(0x1000), None);
+}
+
+#[test]
+fn unsupported_relocations_rejected_unless_lenient() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 16], 16);
+    builder.rela(0x3000, 0x99, 0, 0);
+    builder.rela(0x3008, 3, 0, 0x10);
+    let buf = builder.build();
+
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::UnsupportedReloc { ty: 0x99 }));
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { strict_relocs: false, ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x10_3000), 0);
+    assert_eq!(mmu.word(0x10_3008), 0x10_0010);
+    assert_eq!(loader.skipped_relocs, [(0x10_3000, 0x99)]);
+}
+
+#[test]
+fn shared_pages_mapped_once_with_merged_permission() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x1800, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let maps: alloc::vec::Vec<_> = mmu.calls.iter()
+        .filter(|call| matches!(call, Call::MapExisting { vpn: 1, .. }))
+        .collect();
+    assert_eq!(maps.len(), 1);
+    assert!(matches!(maps[0], Call::MapExisting { perm, .. } if *perm == RWX));
+}
+
+#[test]
+fn frames_allocated_for_page_permission() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), StackConfig::fixed(0x8000, 0x9000), LoadOptions::default()).unwrap();
+    let perms: alloc::vec::Vec<_> = mmu.calls.iter()
+        .filter_map(|call| match call { Call::AllocFor { perm, .. } => Some(*perm), _ => None })
+        .collect();
+    // Text, data shared with the dynamic tables, then the stack
+    assert_eq!(perms, [RX, RW, RW]);
+    assert_eq!(mmu.mapped_vpns(), [1, 2, 8]);
+
+    // Without an override, frames come from `alloc`
+    let mut plain = Plain(MockMmu::new());
+    Loader::load_segments(&buf, &mut plain, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert!(plain.0.calls.iter().all(|call| !matches!(call, Call::AllocFor { .. })));
+}
+
+#[test]
+fn abi_reported_from_elf_flags() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.flags = EF_RISCV_RVC | 0x4;
+    builder.load(0x1000, PF_R, b"text", 4);
+    let loader = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.abi, Some("lp64d"));
+    assert_eq!(loader.attributes, None);
+}
+
+#[test]
+fn relro_protected_after_relocation() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.phdr(PT_GNU_RELRO, PF_R, 0x3000, 8, 8);
+    builder.rela(0x3000, 3, 0, 0x1234);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { protect_relro: true, ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x3000), 0x1234);
+    assert_eq!(mmu.perm(0x3000), Some(R));
+    assert_eq!(loader.relro, Some(0x3000 .. 0x4000));
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { protect_relro: true, lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.perm(0x3000), Some(NO_ACCESS));
+    assert!(loader.relocate_page(&mut mmu, 0x3000));
+    assert_eq!(mmu.word(0x3000), 0x1234);
+    assert_eq!(mmu.perm(0x3000), Some(R));
+}
+
+#[test]
+fn interpreter_loaded_beside_main_image() {
+    let main = relative_image(0x10);
+    let mut interp = ElfBuilder::new(ET_DYN);
+    interp.entry = 0x1004;
+    interp.load(0x1000, PF_R | PF_X, b"ld.so", 5);
+    interp.load(0x2000, PF_R | PF_W, &[0; 8], 8);
+    interp.rela(0x2000, 3, 0, 0x1000);
+    let interp = interp.build();
+
+    let mut mmu = MockMmu::new();
+    let process = Loader::load_with_interpreter(&main, &interp, &mut mmu, 0x4000_0000, no_vdso(), StackConfig::fixed(0x8000, 0x9000), LoadOptions::default()).unwrap();
+    assert_eq!(process.entry(), 0x4000_0004);
+    assert_eq!(mmu.word(0x3000), 0x10);
+    assert_eq!(mmu.read(0x4000_0000, 5), b"ld.so");
+    assert_eq!(mmu.word(0x4000_1000), 0x4000_0000);
+    assert_eq!(process.main.stack, 0x8000 .. 0x9000);
+
+    let result = Loader::load_with_interpreter(&main, &interp, &mut MockMmu::new(), 0x2000, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::Overlap));
+}
+
+#[test]
+fn regions_record_file_backing() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 0x100);
+    let buf = builder.build();
+
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let data = loader.regions.iter().find(|region| region.vaddr.start == 0x10_2000).unwrap();
+    assert_eq!((data.file_offset, data.file_size), (segment_offset(&buf, 0x2000), 4));
+    assert_eq!(&buf[data.file_offset .. data.file_offset + 4], b"data");
+}
+
+/// File offset of the PT_LOAD segment at `vaddr`
+fn segment_offset(buf: &[u8], vaddr: usize) -> usize {
+    Header::parse(buf).unwrap().phdrs.iter().find(|ph| ph.vaddr == vaddr).unwrap().offset
+}
+
+#[test]
+fn randomized_stack_top_within_window() {
+    let buf = relative_image(0);
+    let mut mmu = MockMmu::new();
+    let stack = StackConfig::randomized(0x2000, 0x10_0000, 0x20_0008);
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), stack, LoadOptions::default()).unwrap();
+    assert_eq!(loader.stack_top, 0x20_0000);
+    assert_eq!(loader.stack, 0x1f_e000 .. 0x20_0000);
+
+    let mut entropy = || 3;
+    let options = LoadOptions { entropy: Some(&mut entropy), ..LoadOptions::default() };
+    let stack = StackConfig::randomized(0x2000, 0x10_0001, 0x20_0000);
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), stack, options).unwrap();
+    assert_eq!(loader.stack_top, 0x10_0040);
+    assert_eq!(loader.stack, 0xf_e000 .. 0x10_1000);
+    assert_eq!(loader.stack_top % 16, 0);
+
+    // The window lies below the image
+    let stack = StackConfig::randomized(0x1000, 0, 0x2000);
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), stack, LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::NoSpace));
+}
+
+#[test]
+fn deferred_relocations_indexed_by_page() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x1010], 0x1010);
+    builder.rela(0x3000, 3, 0, 1);
+    builder.rela(0x3ff8, 3, 0, 2);
+    builder.rela(0x4008, 3, 0, 3);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(loader.page_relocations(3), 0 .. 2);
+    assert_eq!(loader.page_relocations(4), 2 .. 3);
+    assert_eq!(loader.page_relocations(5), 0 .. 0);
+
+    assert!(loader.relocate_page(&mut mmu, 0x4000));
+    assert_eq!(mmu.word(0x4008), 3);
+    assert_eq!(mmu.word(0x3ff8), 0);
+}
+
+#[test]
+fn load_stages_sections_by_address() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    builder.section(b".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x2000, 4);
+    builder.section(b".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0x1000, 4);
+    let buf = builder.build();
+
+    let loader = Loader::load(&buf, &mut MockMmu::new(), no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let starts: alloc::vec::Vec<_> = loader.regions.iter().map(|region| region.vaddr.start).collect();
+    assert!(starts.is_sorted());
+    assert_eq!(starts[.. 2], [0x1000, 0x2000]);
+}
+
+#[test]
+fn load_merges_shared_page_permissions_in_any_section_order() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    let mut content = [0; 0x810];
+    content[.. 4].copy_from_slice(b"text");
+    content[0x800 .. 0x804].copy_from_slice(b"data");
+    builder.load(0x1000, PF_R | PF_W | PF_X, &content, 0x810);
+    // Descending addresses, with an empty .bss that maps nothing
+    builder.section(b".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 0x1810, 0);
+    builder.section(b".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1800, 0x10);
+    builder.section(b".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0x1000, 4);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    Loader::load(&buf, &mut mmu, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.perm(0x1000), Some(RWX));
+    assert_eq!(mmu.read(0x1000, 4), b"text");
+    assert_eq!(mmu.read(0x1800, 4), b"data");
+}
+
+#[test]
+fn require_huge_rejects_small_vdso_leaves() {
+    let empty = ElfBuilder::new(ET_DYN).build();
+    let options = || LoadOptions { require_huge: true, ..LoadOptions::default() };
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(512, 512) * 4096;
+    let config = VDSOConfig { start, end: start + HUGE, target: Some(0x4000_0000), lookup: no_lookup };
+    Loader::load(&empty, &mut mmu, Some(config), empty_stack(), options()).unwrap();
+
+    let mut plain = Plain(MockMmu::new());
+    let start = plain.0.reserve(512, 512) * 4096;
+    let config = VDSOConfig { start, end: start + HUGE, target: Some(0x4000_0000), lookup: no_lookup };
+    let result = Loader::load(&empty, &mut plain, Some(config), empty_stack(), options());
+    assert_eq!(result.err(), Some(LoadError::HugePageUnsupported));
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(514, 512) * 4096;
+    let config = VDSOConfig { start, end: start + HUGE + 0x2000, target: Some(0x4000_0000), lookup: no_lookup };
+    let result = Loader::load(&empty, &mut mmu, Some(config), empty_stack(), options());
+    assert_eq!(result.err(), Some(LoadError::HugePageUnsupported));
+    assert!(mmu.calls.is_empty());
+}
+
+#[test]
+fn auto_placed_vdso_aligned_for_huge_pages() {
+    let mut mmu = MockMmu::new();
+    let ppn = mmu.reserve(513, 512);
+    let start = ppn * 4096;
+    let config = VDSOConfig { start, end: start + HUGE + 0x1000, target: None, lookup: no_lookup };
+    let loader = Loader::load_segments(&relative_image(0), &mut mmu, 0, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+
+    assert_eq!(loader.vdso_base, Some(HUGE));
+    assert_eq!(mmu.calls.iter().filter(|call| matches!(call, Call::MapAtLevel { level: 1, .. })).count(), 1);
+
+    // Without huge pages, the VDSO goes right above the image
+    let mut plain = Plain(MockMmu::new());
+    let start = plain.0.reserve(513, 512) * 4096;
+    let config = VDSOConfig { start, end: start + HUGE + 0x1000, target: None, lookup: no_lookup };
+    let loader = Loader::load_segments(&relative_image(0), &mut plain, 0, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.vdso_base, Some(0x5000));
+}
+
+#[test]
+fn image_span_includes_gaps() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1800, PF_R | PF_X, b"text", 4);
+    builder.load(0x4000, PF_R | PF_W, b"data", 0x1001);
+    assert_eq!(Loader::image_span(&builder.build()), Ok(0x5000));
+    assert_eq!(Loader::image_span(&ElfBuilder::new(ET_DYN).build()), Err(LoadError::InvalidElf));
+}
+
+#[test]
+fn relro_range_reported_page_rounded() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x1100], 0x1100);
+    builder.phdr(PT_GNU_RELRO, PF_R, 0x3010, 0x1000, 0x1000);
+    let loader = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.relro, Some(0x10_3000 .. 0x10_5000));
+}
+
+#[test]
+fn unresolved_symbols_fill_buffer() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x28], 0x28);
+    let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    let weak = builder.sym(b"maybe", 0, STB_WEAK, 0);
+    let known = builder.sym(b"known", 0, STB_GLOBAL, 0);
+    let exit = builder.sym(b"exit", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_64, puts, 0);
+    builder.rela(0x3008, R_RISCV_JUMP_SLOT, weak, 0);
+    builder.rela(0x3010, R_RISCV_64, known, 0);
+    builder.rela(0x3018, R_RISCV_JUMP_SLOT, puts, 0);
+    builder.rela(0x3020, R_RISCV_64, exit, 0);
+    let buf = builder.build();
+    let resolve = |name: &[u8]| (name == b"known").then_some(0x1000);
+
+    let mut out: [&[u8]; 4] = [&[]; 4];
+    assert_eq!(Loader::unresolved_symbols(&buf, resolve, &mut out), Ok((2, false)));
+    assert_eq!(out[.. 2], [b"puts" as &[u8], b"exit"]);
+
+    let mut out: [&[u8]; 1] = [&[]; 1];
+    assert_eq!(Loader::unresolved_symbols(&buf, resolve, &mut out), Ok((1, true)));
+    assert_eq!(out[0], b"puts");
+}
+
+#[test]
+fn map_descending_reverses_map_order() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, &[0; 0x3000], 0x3000);
+    let buf = builder.build();
+    let mapped_order = |map_descending| {
+        let mut mmu = MockMmu::new();
+        let options = LoadOptions { map_descending, ..LoadOptions::default() };
+        Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), StackConfig::fixed(0x8000, 0xa000), options).unwrap();
+        mmu.calls.iter()
+            .filter_map(|call| match call { Call::MapExisting { vpn, .. } => Some(*vpn), _ => None })
+            .collect::<alloc::vec::Vec<_>>()
+    };
+    assert_eq!(mapped_order(false), [1, 2, 3, 8, 9]);
+    assert_eq!(mapped_order(true), [3, 2, 1, 9, 8]);
+}
+
+#[test]
+fn entry_perm_reports_entry_page() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.entry = 0x1004;
+    builder.load(0x1000, PF_R | PF_X, b"text", 8);
+    let buf = builder.build();
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.entry, 0x10_1004);
+    assert_eq!(loader.entry_perm, RX);
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.entry = 0x8000;
+    builder.load(0x1000, PF_R | PF_X, b"text", 8);
+    let loader = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.entry_perm, NO_ACCESS);
+}
+
+#[test]
+fn load_copies_sections_and_zeroes_bss() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"data", 0x20);
+    builder.section(b".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1000, 4);
+    builder.section(b".bss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE, 0x1004, 0x1c);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    mmu.fill_free(0xaa);
+    Loader::load(&buf, &mut mmu, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x1000, 4), b"data");
+    assert_eq!(mmu.read(0x1004, 0x1c), [0; 0x1c]);
+    assert_eq!(mmu.perm(0x1000), Some(RW));
+}
+
+#[test]
+fn tls_template_reports_alignment() {
+    for (p_align, align) in [(64, 64), (0, 1)] {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x1000, PF_R | PF_W, b"tdata", 5);
+        builder.phdr(PT_TLS, PF_R, 0x1000, 5, 8).align = p_align;
+        let loader = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+        assert_eq!(loader.tls.unwrap().align, align);
+    }
+}
+
+#[test]
+fn relocation_count_reported() {
+    let loader = Loader::load_segments(&relative_image(0x10), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.relocations, 1);
+
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x1000, PF_R | PF_X, b"text", 4);
+    let buf = builder.build();
+    assert!(Header::parse(&buf).unwrap().dynamic().is_none());
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.relocations, 0);
+}
+
+#[test]
+fn contiguous_segments_coalesced() {
+    let mut content = [0; 0x50];
+    for (idx, byte) in content.iter_mut().enumerate() {
+        *byte = idx as u8 / 0x10 + 1;
+    }
+    // Segments carved out of a single run of file content
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, &content, 0x50);
+    builder.phdr(PT_LOAD, PF_R | PF_X, 0x1010, 0x10, 0x10);
+    builder.phdr(PT_LOAD, PF_R | PF_W, 0x1020, 0x10, 0x20);
+    builder.phdr(PT_LOAD, PF_R | PF_W, 0x1040, 0x10, 0x10);
+    builder.phdrs[0].filesz = 0x10;
+    builder.phdrs[0].memsz = 0x10;
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let regions: alloc::vec::Vec<_> = loader.regions.iter().map(|region| region.vaddr.clone()).collect();
+    // The zero-filled tail of the third segment keeps the fourth apart
+    assert_eq!(regions[.. 3], [0x1000 .. 0x1020, 0x1020 .. 0x1040, 0x1040 .. 0x1050]);
+    assert_eq!(mmu.read(0x100f, 2), [1, 2]);
+    assert_eq!(mmu.read(0x1030, 1), [0]);
+}
+
+#[test]
+fn wx_window_reported() {
+    let wx = |flags, rela| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x1000, flags, &[0; 8], 8);
+        if rela {
+            builder.rela(0x1000, 3, 0, 0);
+        }
+        Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap().had_wx_window
+    };
+    assert!(!wx(PF_R | PF_X, false));
+    assert!(wx(PF_R | PF_W | PF_X, false));
+    // Text relocation
+    assert!(wx(PF_R | PF_X, true));
+    assert!(!wx(PF_R | PF_W, true));
+}
+
+#[test]
+fn apply_reloc_entry_patches_one_slot() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    let ext = builder.sym(b"ext", 0, STB_GLOBAL, 0);
+    let buf = builder.build();
+    let header = Header::parse(&buf).unwrap();
+    let file_map = FileMap::new(&header.phdrs);
+    let dynamic = Dynamic::parse(&buf, header.dynamic().unwrap(), &file_map).unwrap();
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let resolve = |name: &[u8]| (name == b"ext").then_some(0x4000);
+    let ent = Elf64RELA { offset: 0x3000, info: ext << 32 | R_RISCV_64, addend: 8 };
+    assert_eq!(Loader::apply_reloc_entry(&ent, &dynamic, 0x10_0000, resolve, &mut mmu), Ok(RelocOutcome::Applied { vaddr: 0x10_3000, value: 0x4008 }));
+    assert_eq!(mmu.word(0x10_3000), 0x4008);
+
+    let none = Elf64RELA { offset: 0x3000, info: 0, addend: 0 };
+    assert_eq!(Loader::apply_reloc_entry(&none, &dynamic, 0x10_0000, resolve, &mut mmu), Ok(RelocOutcome::Skipped));
+    let unmapped = Elf64RELA { offset: 0x9000, info: 3, addend: 0 };
+    assert_eq!(Loader::apply_reloc_entry(&unmapped, &dynamic, 0x10_0000, resolve, &mut mmu), Err(LoadError::RelocOutOfRange));
+    assert_eq!(Loader::apply_reloc_entry(&ent, &dynamic, 0x10_0000, no_lookup, &mut mmu), Err(LoadError::UnresolvedSymbol));
+}
+
+#[test]
+fn phdr_reported_with_bias() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.phdr(PT_LOAD, PF_R, 0, 0x1000, 0x1000);
+    let loader = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(loader.phdr, Some(0x10_0040));
+}
+
+#[test]
+fn load_skips_tls_sections() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"datatdata", 9);
+    builder.section(b".data", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1000, 4);
+    builder.section(b".tdata", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 0x1004, 5);
+    builder.section(b".tbss", SHT_NOBITS, SHF_ALLOC | SHF_WRITE | SHF_TLS, 0x1009, 0x2000);
+    builder.phdr(PT_TLS, PF_R, 0x1004, 5, 0x2005);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load(&buf, &mut mmu, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert!(loader.regions.iter().all(|region| region.vaddr.start != 0x1004 && region.vaddr.start != 0x1009));
+    assert_eq!(mmu.perm(0x2000), None);
+    assert_eq!(&loader.tls.unwrap().image[.. 5], b"tdata");
+}
+
+#[test]
+fn rebind_vdso_shifts_bound_slots() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 16], 16);
+    let sym = builder.sym(b"vdso_fn", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_JUMP_SLOT, sym, 0);
+    builder.rela(0x3008, R_RISCV_RELATIVE, 0, 0x10);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"vdso_fn").then_some(start + 0x10);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+    let mut loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+
+    loader.rebind_vdso(&mut mmu, 0x5000_0000);
+    assert_eq!(loader.vdso_base, Some(0x5000_0000));
+    assert_eq!(mmu.word(0x3000), 0x5000_0010);
+    assert_eq!(mmu.word(0x3008), 0x10);
+}
+
+#[test]
+fn bind_now_overrides_lazy_relocation() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.rela(0x3000, 3, 0, 0x10);
+    builder.dyn_entries.push((DT_FLAGS_1, 1));
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.perm(0x3000), Some(RW));
+    assert_eq!(mmu.word(0x3000), 0x10);
+    assert!(!loader.relocate_page(&mut mmu, 0x3000));
+}
+
+/// An image binding `puts` through a PLT relocation of the GOT slot at 0x3008
+fn plt_image() -> alloc::vec::Vec<u8> {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 16], 16);
+    let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    builder.plt_rela(0x3008, R_RISCV_JUMP_SLOT, puts, 0);
+    builder.build()
+}
+
+#[test]
+fn plt_relocations_applied_with_rela() {
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"puts").then_some(start + 0x20);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+    let loader = Loader::load_segments(&plt_image(), &mut mmu, 0, Some(config), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x3000), 0x10);
+    assert_eq!(mmu.word(0x3008), 0x4000_0020);
+    assert_eq!(loader.relocations, 2);
+}
+
+#[test]
+fn plt_relocations_checked_and_reported() {
+    let issues = Loader::check_relocations(&plt_image(), no_lookup).unwrap();
+    assert_eq!(issues.len(), 1);
+    assert!(matches!(issues[0], RelocIssue::Unresolved { .. }));
+
+    let mut out: [&[u8]; 2] = [&[]; 2];
+    let buf = plt_image();
+    assert_eq!(Loader::unresolved_symbols(&buf, no_lookup, &mut out), Ok((1, false)));
+    assert_eq!(out[0], b"puts");
+}
+
+#[test]
+fn weak_undefined_symbols_recorded() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x18], 0x18);
+    let weak = builder.sym(b"maybe", 0, STB_WEAK, 0);
+    let known = builder.sym(b"known", 0, STB_WEAK, 0);
+    builder.rela(0x3000, R_RISCV_64, weak, 0);
+    builder.rela(0x3008, R_RISCV_JUMP_SLOT, weak, 0);
+    builder.rela(0x3010, R_RISCV_64, known, 0);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"known").then_some(start);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+    let options = LoadOptions { record_weak_undef: true, ..LoadOptions::default() };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, Some(config), empty_stack(), options).unwrap();
+    assert_eq!(loader.weak_undefined, [b"maybe".to_vec()]);
+    assert_eq!(mmu.word(0x3000), 0);
+    assert_eq!(mmu.word(0x3010), 0x4000_0000);
+
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert!(loader.weak_undefined.is_empty());
+}
+
+#[test]
+fn regions_split_file_and_zero_bytes() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x2000, PF_R | PF_W, &[1; 0x1100], 0x3800);
+    let buf = builder.build();
+
+    let loader = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let data = loader.regions.iter().find(|region| region.vaddr.start == 0x2000).unwrap();
+    assert_eq!((data.file_size, data.zero_size()), (0x1100, 0x2700));
+    assert_eq!(data.zero_pages(), 4 .. 6);
+}
+
+/// MMU relying on the default methods, forwarding the required ones to a `MockMmu`
+struct DefaultMmu<'a>(&'a mut MockMmu);
+
+impl MMU for DefaultMmu<'_> {
+    type AllocatedPage = Frame;
+
+    fn alloc(&mut self) -> Frame {
+        self.0.alloc()
+    }
+
+    fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        self.0.map_existing(ppn, vpn, perm)
+    }
+
+    fn translate(&self, vaddr: usize) -> Option<usize> {
+        self.0.translate(vaddr)
+    }
+}
+
+#[test]
+fn default_map_range_maps_each_page() {
+    let mut mmu = MockMmu::new();
+    DefaultMmu(&mut mmu).map_range(0x100, 0x10, 3, RW);
+    assert_eq!(mmu.calls, [
+        Call::MapExisting { ppn: 0x100, vpn: 0x10, perm: RW },
+        Call::MapExisting { ppn: 0x101, vpn: 0x11, perm: RW },
+        Call::MapExisting { ppn: 0x102, vpn: 0x12, perm: RW },
+    ]);
+}
+
+#[test]
+fn contiguous_frames_mapped_as_one_range() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, b"data", 0x3000);
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    mmu.contiguous = true;
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+
+    let ppn = mmu.ppn(0x1000).unwrap();
+    assert!(mmu.calls.contains(&Call::AllocContiguous { ppn, count: 3 }));
+    let ranges: alloc::vec::Vec<_> = mmu.calls.iter().filter(|call| matches!(call, Call::MapRange { .. } | Call::MapExisting { .. })).collect();
+    assert_eq!(ranges, [&Call::MapRange { ppn, vpn: 1, count: 3, perm: RW }]);
+    assert_eq!(mmu.read(0x1000, 4), b"data");
+    assert_eq!(mmu.word(0x2000), 0);
+}
+
+#[test]
+fn symbol_overrides_take_precedence_over_vdso() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x10], 0x10);
+    let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    let exit = builder.sym(b"exit", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_64, puts, 0);
+    builder.plt_rela(0x3008, R_RISCV_JUMP_SLOT, exit, 0);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let lookup = |name: &[u8]| (name == b"puts" || name == b"exit").then_some(start + 0x10);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+    let overrides: &[(&[u8], usize)] = &[(b"puts", 0x7000_1234)];
+    let options = LoadOptions { symbol_overrides: overrides, ..LoadOptions::default() };
+    Loader::load_segments(&buf, &mut mmu, 0, Some(config), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x3000), 0x7000_1234);
+    assert_eq!(mmu.word(0x3008), 0x4000_0010);
+}
+
+#[test]
+fn needs_interpreter_only_for_external_dependencies() {
+    assert_eq!(Loader::needs_interpreter(&relative_image(0x1234)), Ok(false));
+    assert_eq!(Loader::needs_interpreter(&ElfBuilder::new(ET_DYN).build()), Ok(false));
+    assert_eq!(Loader::needs_interpreter(&plt_image()), Ok(true));
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    let libc = builder.string(b"libc.so.6");
+    builder.dyn_entries.push((DT_NEEDED, libc));
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    assert_eq!(Loader::needs_interpreter(&builder.build()), Ok(true));
+}
+
+#[test]
+fn null_guard_allows_segments_one_page_up() {
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    let buf = builder.build();
+    let options = || LoadOptions { null_guard: true, ..LoadOptions::default() };
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options()).unwrap();
+    assert_eq!(mmu.perm(0x1000), Some(RX));
+    assert_eq!(mmu.perm(0), None);
+
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x800, PF_R | PF_X, b"code", 4);
+    let result = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), options());
+    assert!(matches!(result, Err(LoadError::NullPage)));
+}
+
+#[test]
+fn unaligned_segments_placed_at_page_granularity() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1010, PF_R | PF_W, b"data", 4).align = 0;
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x1010, 4), b"data");
+    assert_eq!(mmu.perm(0x1000), Some(RW));
+}
+
+#[test]
+fn mappings_confined_to_user_space() {
+    let mut builder = ElfBuilder::new(ET_EXEC);
+    builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    let buf = builder.build();
+    let options = |user_space| LoadOptions { user_space, ..LoadOptions::default() };
+
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), options(0x10_0000 .. 0x4000_0000));
+    assert!(matches!(result, Err(LoadError::OutsideUserSpace)));
+
+    let stack = StackConfig::fixed(0x4000_0000, 0x4000_2000);
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0x10_0000, no_vdso(), stack, options(0x10_0000 .. 0x4000_0000));
+    assert!(matches!(result, Err(LoadError::OutsideUserSpace)));
+
+    let stack = StackConfig::fixed(0x3fff_e000, 0x4000_0000);
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), stack, options(0x10_0000 .. 0x4000_0000)).unwrap();
+    assert_eq!(mmu.perm(0x10_1000), Some(RX));
+}
+
+#[test]
+fn image_hash_follows_loaded_content() {
+    let image = |code: &[u8]| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x1000, PF_R | PF_X, code, code.len());
+        builder.load(0x2000, PF_R | PF_W, b"data", 0x100);
+        builder.build()
+    };
+    let hash = Loader::image_hash(&image(b"code")).unwrap();
+    assert_eq!(Loader::image_hash(&image(b"code")), Ok(hash));
+    assert_ne!(Loader::image_hash(&image(b"cope")), Ok(hash));
+
+    // Bytes outside the loaded segments are not hashed
+    let mut buf = image(b"code");
+    buf.extend_from_slice(b"trailing debug info");
+    assert_eq!(Loader::image_hash(&buf), Ok(hash));
+}
+
+#[test]
+fn interpreter_relocated_against_vdso_and_kernel_symbols() {
+    let main = relative_image(0x10);
+    let mut interp = ElfBuilder::new(ET_DYN);
+    interp.load(0x1000, PF_R | PF_X, b"ld.so", 5);
+    interp.load(0x2000, PF_R | PF_W, &[0; 0x10], 0x10);
+    let clock = interp.sym(b"clock", 0, STB_GLOBAL, 0);
+    let syscall = interp.sym(b"syscall", 0, STB_GLOBAL, 0);
+    interp.plt_rela(0x2000, R_RISCV_JUMP_SLOT, clock, 0);
+    interp.plt_rela(0x2008, R_RISCV_JUMP_SLOT, syscall, 0);
+    let interp = interp.build();
+
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let vdso_lookup = |name: &[u8]| (name == b"clock").then_some(start + 0x20);
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x2000_0000), lookup: vdso_lookup };
+    let mut kernel = |name: &[u8]| (name == b"syscall").then_some(0xffff_0000);
+    let options = LoadOptions { lookup: Some(&mut kernel), ..LoadOptions::default() };
+    Loader::load_with_interpreter(&main, &interp, &mut mmu, 0x4000_0000, Some(config), StackConfig::fixed(0x8000, 0x9000), options).unwrap();
+    assert_eq!(mmu.word(0x4000_1000), 0x2000_0020);
+    assert_eq!(mmu.word(0x4000_1008), 0xffff_0000);
+}
+
+#[test]
+fn per_segment_guard_keeps_following_page_unmapped() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"code", 0x1800);
+    builder.load(0x4000, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+    let options = || LoadOptions { per_segment_guard: true, ..LoadOptions::default() };
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), StackConfig::fixed(0x8000, 0x9000), options()).unwrap();
+    assert_eq!(mmu.perm(0x2000), Some(RX));
+    assert_eq!(mmu.perm(0x3000), None);
+    assert_eq!(mmu.perm(0x4000), Some(RW));
+    assert_eq!(mmu.perm(0x5000), None);
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    let result = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), options());
+    assert!(matches!(result, Err(LoadError::GuardPageMapped)));
+
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), StackConfig::fixed(0x5000, 0x6000), options());
+    assert!(matches!(result, Err(LoadError::GuardPageMapped)));
+}
+
+#[test]
+fn per_segment_guard_follows_segments_when_loading_sections() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, &[0x13; 0x1010], 0x1010);
+    builder.section(b".text", SHT_PROGBITS, SHF_ALLOC | SHF_EXECINSTR, 0x1000, 0x1000);
+    builder.section(b".rodata", SHT_PROGBITS, SHF_ALLOC, 0x2000, 0x10);
+    let buf = builder.build();
+    let options = LoadOptions { per_segment_guard: true, ..LoadOptions::default() };
+    let mut mmu = MockMmu::new();
+    Loader::load(&buf, &mut mmu, no_vdso(), StackConfig::fixed(0x8000, 0x9000), options).unwrap();
+    assert_eq!(mmu.perm(0x2000), Some(R));
+    assert_eq!(mmu.perm(0x3000), None);
+}
+
+#[test]
+fn frozen_instances_share_frames_until_written() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 4);
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    let frozen = loader.freeze(&mut mmu);
+    assert_eq!(mmu.perm(0x2000), Some(R));
+
+    let mut first = MockMmu::new();
+    let mut second = MockMmu::new();
+    frozen.instantiate(&mut first);
+    frozen.instantiate(&mut second);
+    assert_eq!(first.ppn(0x1000), mmu.ppn(0x1000));
+    assert_eq!(second.ppn(0x1000), mmu.ppn(0x1000));
+    assert_eq!(second.ppn(0x2000), mmu.ppn(0x2000));
+    assert_eq!(first.perm(0x2000), Some(R));
+
+    assert!(frozen.copy_on_write(&mut first, 0x2000));
+    assert!(!frozen.copy_on_write(&mut first, 0x2000));
+    assert!(!frozen.copy_on_write(&mut first, 0x1000));
+    assert_eq!(first.perm(0x2000), Some(RW));
+    assert_ne!(first.ppn(0x2000), mmu.ppn(0x2000));
+    first.write(0x2000, b"DATA");
+    assert_eq!(first.read(0x2000, 4), b"DATA");
+    assert_eq!(second.read(0x2000, 4), b"data");
+}
+
+#[test]
+fn inconsistent_rela_table_rejected() {
+    let image = |entries: &[(isize, usize)]| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+        builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+        builder.dyn_entries.extend_from_slice(entries);
+        builder.build()
+    };
+    for entries in [&[(DT_RELASZ, 25usize)] as &[_], &[(DT_RELAENT, 16)], &[(DT_RELASZ, 24 * 0x1000)]] {
+        let result = Loader::load_segments(&image(entries), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+        assert!(matches!(result, Err(LoadError::BadRelocTable)), "{:?}", entries);
+    }
+    assert!(Loader::load_segments(&image(&[]), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).is_ok());
+}
+
+/// An image at `base` with a TLS segment holding `tdata`
+fn tls_image(tdata: &[u8], base: usize) -> ElfBuilder {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(base, PF_R | PF_W, tdata, tdata.len());
+    builder.phdr(PT_TLS, PF_R, base, tdata.len(), tdata.len());
+    builder
+}
+
+#[test]
+fn tls_modules_numbered_from_main_program() {
+    let main = tls_image(b"main", 0x1000).build();
+    let interp = tls_image(b"ld.so", 0x1000).build();
+    let process = Loader::load_with_interpreter(&main, &interp, &mut MockMmu::new(), 0x4000_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!((process.main.tls_module, process.interpreter.tls_module), (Some(1), Some(2)));
+    assert_eq!(process.tls_block(1).unwrap().image, b"main");
+    assert_eq!(process.tls_block(2).unwrap().image, b"ld.so");
+    assert!(process.tls_block(3).is_none());
+
+    let process = Loader::load_with_interpreter(&relative_image(0), &interp, &mut MockMmu::new(), 0x4000_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!((process.main.tls_module, process.interpreter.tls_module), (None, Some(1)));
+}
+
+#[test]
+fn tls_relocations_resolved_for_module() {
+    let image = |base: usize| {
+        let mut builder = tls_image(b"tdata", base);
+        builder.load(base + 0x2000, PF_R | PF_W, &[0; 0x18], 0x18);
+        let counter = builder.sym(b"counter", 4, STB_GLOBAL, SHN_TEXT);
+        builder.rela(base + 0x2000, R_RISCV_TLS_DTPMOD64, 0, 0);
+        builder.rela(base + 0x2008, R_RISCV_TLS_DTPREL64, counter, 1);
+        builder.rela(base + 0x2010, R_RISCV_TLS_TPREL64, counter, 1);
+        builder.build()
+    };
+    let main = image(0x1000);
+    let interp = image(0x1000);
+    let mut mmu = MockMmu::new();
+    let stack = StackConfig::fixed(0x8000, 0x9000);
+    let options = LoadOptions { strict_relocs: false, ..LoadOptions::default() };
+    let process = Loader::load_with_interpreter(&main, &interp, &mut mmu, 0x4000_0000, no_vdso(), stack, options).unwrap();
+    assert_eq!(mmu.word(0x3000), 1);
+    assert_eq!(mmu.word(0x3008), 5usize.wrapping_sub(TLS_DTV_OFFSET));
+    assert_eq!(mmu.word(0x3010), 5);
+
+    // Only the main program has a known static TLS offset
+    assert_eq!(mmu.word(0x4000_2000), 2);
+    assert_eq!(mmu.word(0x4000_2008), 5usize.wrapping_sub(TLS_DTV_OFFSET));
+    assert_eq!(mmu.word(0x4000_2010), 0);
+    assert_eq!(process.interpreter.skipped_relocs, [(0x4000_2010, R_RISCV_TLS_TPREL64)]);
+}
+
+#[test]
+fn check_vdso_lists_every_missing_symbol() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x28], 0x28);
+    let clock = builder.sym(b"clock", 0, STB_GLOBAL, 0);
+    let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+    let exit = builder.sym(b"exit", 0, STB_GLOBAL, 0);
+    let weak = builder.sym(b"maybe", 0, STB_WEAK, 0);
+    builder.rela(0x3000, R_RISCV_64, clock, 0);
+    builder.rela(0x3008, R_RISCV_64, puts, 0);
+    builder.rela(0x3010, R_RISCV_64, weak, 0);
+    builder.plt_rela(0x3018, R_RISCV_JUMP_SLOT, exit, 0);
+    builder.plt_rela(0x3020, R_RISCV_JUMP_SLOT, puts, 0);
+    let buf = builder.build();
+
+    let mut config = VDSOConfig { start: 0, end: 0x1000, target: None, lookup: |name: &[u8]| (name == b"clock").then_some(0x10) };
+    let missing = Loader::check_vdso(&buf, &mut config).unwrap();
+    assert_eq!(missing, [
+        MissingSymbol { name: b"puts".to_vec(), offsets: alloc::vec![0x3008, 0x3020] },
+        MissingSymbol { name: b"exit".to_vec(), offsets: alloc::vec![0x3018] },
+    ]);
+}
+
+#[test]
+fn sub_word_relocations_write_their_width() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0xaa; 0x10], 0x10);
+    let value = builder.sym(b"value", 0x1234, STB_GLOBAL, SHN_ABS);
+    builder.rela(0x3002, R_RISCV_32, value, 0x10);
+    builder.rela(0x3009, R_RISCV_SET16, value, 0);
+    let byte = builder.sym(b"byte", 0x34, STB_GLOBAL, SHN_ABS);
+    builder.rela(0x300c, R_RISCV_SET8, byte, 0);
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x3000, 0x10), [0xaa, 0xaa, 0x44, 0x12, 0, 0, 0xaa, 0xaa, 0xaa, 0x34, 0x12, 0xaa, 0x34, 0xaa, 0xaa, 0xaa]);
+}
+
+#[test]
+fn sub_word_relocations_checked_for_overflow_and_symbols() {
+    let image = |value: usize| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+        let sym = builder.sym(b"value", value, STB_GLOBAL, SHN_ABS);
+        builder.rela(0x3000, R_RISCV_SET16, sym, 0);
+        builder.build()
+    };
+    let load = |buf: &[u8]| Loader::load_segments(buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default()).map(|_| ());
+    assert_eq!(load(&image(0xffff)), Ok(()));
+    assert_eq!(load(&image(-0x8000isize as usize)), Ok(()));
+    assert_eq!(load(&image(0x1_0000)), Err(LoadError::RelocOverflow { ty: R_RISCV_SET16 }));
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    let ext = builder.sym(b"ext", 0, STB_GLOBAL, 0);
+    builder.rela(0x3000, R_RISCV_32, ext, 0);
+    let buf = builder.build();
+    let mut out: [&[u8]; 1] = [&[]; 1];
+    assert_eq!(Loader::unresolved_symbols(&buf, no_lookup, &mut out), Ok((1, false)));
+    assert_eq!(out[0], b"ext");
+}
+
+#[test]
+fn maps_label_file_anon_stack_and_vdso() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    builder.load(0x2000, PF_R | PF_W, b"data", 0x2000);
+    let buf = builder.build();
+    let mut mmu = MockMmu::new();
+    let start = mmu.reserve(1, 1) * 4096;
+    let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup: no_lookup };
+    let loader = Loader::load_segments(&buf, &mut mmu, 0x10_0000, Some(config), StackConfig::fixed(0x8000, 0xa000), LoadOptions::default()).unwrap();
+
+    let code = segment_offset(&buf, 0x1000);
+    let data = segment_offset(&buf, 0x2000);
+    assert_eq!(loader.maps(), [
+        MapEntry { vaddr: 0x8000 .. 0xa000, perm: RW, kind: MapKind::Stack },
+        MapEntry { vaddr: 0x10_1000 .. 0x10_2000, perm: RX, kind: MapKind::File { offset: code } },
+        MapEntry { vaddr: 0x10_2000 .. 0x10_3000, perm: RW, kind: MapKind::File { offset: data } },
+        MapEntry { vaddr: 0x10_3000 .. 0x10_4000, perm: RW, kind: MapKind::Anon },
+        MapEntry { vaddr: 0x4000_0000 .. 0x4000_1000, perm: RX, kind: MapKind::Vdso },
+    ]);
+}
+
+#[test]
+fn incomplete_got_rejected() {
+    let image = |resolved: bool| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(0x3000, PF_R | PF_W, &[0; 0x18], 0x18);
+        builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x3000, 0x18);
+        let puts = builder.sym(b"puts", 0, STB_GLOBAL, 0);
+        let called = builder.sym(if resolved { b"puts" } else { b"exit" }, 0, STB_GLOBAL, 0);
+        let maybe = builder.sym(b"maybe", 0, STB_WEAK, 0);
+        builder.rela(0x3000, R_RISCV_64, puts, 0);
+        builder.rela(0x3008, R_RISCV_JUMP_SLOT, called, 0);
+        builder.rela(0x3010, R_RISCV_64, maybe, 0);
+        builder.build()
+    };
+    let load = |buf: &[u8]| {
+        let mut mmu = MockMmu::new();
+        let start = mmu.reserve(1, 1) * 4096;
+        let lookup = |name: &[u8]| (name == b"puts").then_some(start);
+        let config = VDSOConfig { start, end: start + 4096, target: Some(0x4000_0000), lookup };
+        let options = LoadOptions { assert_got_complete: true, ..LoadOptions::default() };
+        Loader::load_segments(buf, &mut mmu, 0, Some(config), empty_stack(), options).map(|_| ())
+    };
+    assert_eq!(load(&image(true)), Ok(()));
+    assert_eq!(load(&image(false)), Err(LoadError::IncompleteGot { offset: 0x3008 }));
+}
+
+#[test]
+fn statically_resolved_got_slots_may_be_zero() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 0x10], 0x10);
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x3000, 0x10);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    let buf = builder.build();
+    let options = LoadOptions { assert_got_complete: true, ..LoadOptions::default() };
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x3008), 0);
+}
+
+/// MMU translating the page at 0x3000 one frame off
+struct WrongFrameMmu(MockMmu);
+
+impl MMU for WrongFrameMmu {
+    type AllocatedPage = Frame;
+
+    fn alloc(&mut self) -> Frame {
+        self.0.alloc()
+    }
+
+    fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        self.0.map_existing(ppn, vpn, perm)
+    }
+
+    fn translate(&self, vaddr: usize) -> Option<usize> {
+        let paddr = self.0.translate(vaddr)?;
+        Some(if vaddr / 4096 == 3 { paddr + 4096 } else { paddr })
+    }
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "outside of the frame mapped for it")]
+fn relocation_through_wrong_frame_asserts() {
+    let mut mmu = WrongFrameMmu(MockMmu::new());
+    Loader::load_segments(&relative_image(0x10), &mut mmu, 0, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+}
+
+#[test]
+fn max_relocations_rejects_before_mapping() {
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { max_relocations: Some(0), ..LoadOptions::default() };
+    let result = Loader::load_segments(&relative_image(0x10), &mut mmu, 0, no_vdso(), empty_stack(), options);
+    assert!(matches!(result, Err(LoadError::TooManyRelocations)));
+    assert_eq!(mmu.perm(0x3000), None);
+
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { max_relocations: Some(1), ..LoadOptions::default() };
+    Loader::load_segments(&relative_image(0x10), &mut mmu, 0, no_vdso(), empty_stack(), options).unwrap();
+    assert_eq!(mmu.word(0x3000), 0x10);
+}
+
+#[test]
+fn max_relocations_counts_plt_relocations() {
+    let mut mmu = MockMmu::new();
+    let options = LoadOptions { max_relocations: Some(1), ..LoadOptions::default() };
+    let result = Loader::load_segments(&plt_image(), &mut mmu, 0, no_vdso(), empty_stack(), options);
+    assert!(matches!(result, Err(LoadError::TooManyRelocations)));
+}
+
+#[test]
+fn inspect_summarizes_without_mapping() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.entry = 0x1004;
+    builder.load(0x1000, PF_R | PF_X, b"/lib/ld.so\0", 0x1800);
+    builder.phdr(PT_INTERP, PF_R, 0x1000, 11, 11);
+    let libc = builder.string(b"libc.so.6");
+    builder.dyn_entries.push((DT_NEEDED, libc));
+    let buf = builder.build();
+    let info = Loader::inspect(&buf).unwrap();
+    assert_eq!(info.kind, ElfKind::Pie);
+    assert_eq!(info.entry, 0x1004);
+    assert_eq!(info.interpreter, Some(&b"/lib/ld.so"[..]));
+    assert_eq!(info.needed, [&b"libc.so.6"[..]]);
+    assert_eq!(Loader::image_span(&buf), Ok(info.footprint));
+    assert!(info.footprint >= 2 * 4096);
+
+    assert_eq!(Loader::inspect(&relative_image(0x10)).unwrap().kind, ElfKind::SharedObject);
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.dyn_entries.push((DT_FLAGS_1, DF_1_PIE));
+    assert_eq!(Loader::inspect(&builder.build()).unwrap().kind, ElfKind::Pie);
+    assert_eq!(Loader::inspect(&ElfBuilder::new(ET_EXEC).build()).unwrap().kind, ElfKind::Executable);
+}
+
+#[test]
+fn inspect_rejects_dynamic_array_past_file() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R | PF_W, &[0; 16], 16);
+    builder.phdr(PT_DYNAMIC, PF_R | PF_W, 0x1000, 0x10_0000, 16);
+    assert!(matches!(Loader::inspect(&builder.build()), Err(LoadError::BadRelocTable)));
+}
+
+/// An image whose only segment asks for 2 MiB alignment
+fn huge_aligned_image() -> alloc::vec::Vec<u8> {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    let ph = builder.load(0x1000, PF_R | PF_X, b"code", 4);
+    assert_eq!(ph.offset, 0x1000);
+    ph.align = 0x20_0000;
+    builder.section_headers = false;
+    builder.build()
+}
+
+#[test]
+fn required_base_align_follows_largest_segment_alignment() {
+    assert_eq!(Loader::required_base_align(&huge_aligned_image()), Ok(0x20_0000));
+    assert_eq!(Loader::required_base_align(&relative_image(0x10)), Ok(4096));
+}
+
+#[test]
+fn random_bias_is_multiple_of_required_align() {
+    let buf = huge_aligned_image();
+    for entropy in 0 .. 16 {
+        let bias = Loader::random_bias(&buf, 0x10_0000 .. 0x100_0000, &mut || entropy).unwrap();
+        assert_eq!(bias % 0x20_0000, 0);
+        assert!((0x10_0000 .. 0x100_0000).contains(&(bias + 0x1000)));
+    }
+    assert_eq!(Loader::random_bias(&buf, 0x10_0000 .. 0x1f_0000, &mut || 0), Err(LoadError::NoSpace));
+}
+
+#[test]
+fn misaligned_bias_rejected() {
+    let buf = huge_aligned_image();
+    let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0x1000, no_vdso(), empty_stack(), LoadOptions::default());
+    assert!(matches!(result, Err(LoadError::MisalignedBias)));
+
+    let mut mmu = MockMmu::new();
+    Loader::load_segments(&buf, &mut mmu, 0x20_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.read(0x20_1000, 4), b"code");
+}
+
+#[test]
+fn restore_randomizes_stack_with_entropy() {
+    let layout: [(Range<usize>, Perm, &[u8]); 1] = [(0x1000 .. 0x2000, RX, b"text")];
+    let mut entropy = || 3;
+    let stack = StackConfig::randomized(0x2000, 0x10_0000, 0x20_0000);
+    let loader = Loader::restore(&layout, &mut MockMmu::new(), stack, Some(&mut entropy)).unwrap();
+    assert_eq!(loader.stack_top, 0x10_0030);
+
+    let stack = StackConfig::randomized(usize::MAX, 0x10_0000, 0x20_0000);
+    assert_eq!(Loader::restore(&layout, &mut MockMmu::new(), stack, None).err(), Some(LoadError::NoSpace));
+}
+
+#[test]
+fn rel_table_rejected_as_unsupported() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    let mut rel = [0; 16];
+    rel[.. 8].copy_from_slice(&0x3000u64.to_le_bytes());
+    rel[8 ..].copy_from_slice(&(R_RISCV_RELATIVE as u64).to_le_bytes());
+    builder.load(0x4000, PF_R, &rel, 16);
+    builder.dyn_entries.extend([(DT_REL, 0x4000), (DT_RELSZ, 16), (DT_RELENT, 16)]);
+    let result = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::UnsupportedReloc { ty: R_RISCV_RELATIVE }));
+}
+
+#[test]
+fn relocation_count_excludes_got0_and_includes_tls_template() {
+    let mut builder = tls_image(&[0; 8], 0x2000);
+    builder.load(0x1000, PF_R | PF_W, &[0; 0x10], 0x10);
+    builder.section(b".got", SHT_PROGBITS, SHF_ALLOC | SHF_WRITE, 0x1000, 0x10);
+    builder.rela(0x1008, R_RISCV_RELATIVE, 0, 0x10);
+    builder.rela(0x2000, R_RISCV_RELATIVE, 0, 0x20);
+    let mut buf = builder.build();
+    let header = Header::parse(&buf).unwrap();
+    let dynamic_vaddr = header.dynamic_vaddr().unwrap();
+    let got = crate::elf::FileMap::new(&header.phdrs).offset_of(0x1000).unwrap();
+    buf[got .. got + 8].copy_from_slice(&dynamic_vaddr.to_le_bytes());
+
+    let mut mmu = MockMmu::new();
+    let loader = Loader::load_segments(&buf, &mut mmu, 0x10_0000, no_vdso(), empty_stack(), LoadOptions::default()).unwrap();
+    assert_eq!(mmu.word(0x10_1000), 0x10_0000 + dynamic_vaddr);
+    assert_eq!(mmu.word(0x10_1008), 0x10_0010);
+    assert_eq!(loader.tls.as_ref().unwrap().image, 0x10_0020u64.to_le_bytes());
+    assert_eq!(loader.relocations, 2);
+}
+
+#[test]
+fn overflowing_segments_rejected_without_panicking() {
+    let image = |vaddr, memsz| {
+        let mut builder = ElfBuilder::new(ET_DYN);
+        builder.load(vaddr, PF_R, b"data", 4).memsz = memsz;
+        builder.build()
+    };
+    for (vaddr, memsz) in [(0x1000, usize::MAX), (usize::MAX - 0xfff, 0x1000)] {
+        let buf = image(vaddr, memsz);
+        assert_eq!(Loader::inspect(&buf).err(), Some(LoadError::InvalidElf), "{:#x} {:#x}", vaddr, memsz);
+        let result = Loader::load_segments(&buf, &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+        assert_eq!(result.err(), Some(LoadError::InvalidElf), "{:#x} {:#x}", vaddr, memsz);
+    }
+    // Spanning the whole address space, whose size in bytes doesn't fit
+    assert_eq!(Loader::inspect(&image(0, usize::MAX - 0x10)).err(), Some(LoadError::InvalidElf));
+
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x1000, PF_R, b"data", 4).filesz = usize::MAX;
+    let result = Loader::load_segments(&builder.build(), &mut MockMmu::new(), 0, no_vdso(), empty_stack(), LoadOptions::default());
+    assert_eq!(result.err(), Some(LoadError::InvalidElf));
+}
@@ -1,4 +1,6 @@
-const PAGE_SIZE: usize = 4096;
+use crate::elf::TARGET;
+
+pub const PAGE_SIZE: usize = TARGET.page_size;
 const PAGE_SIZE_BITS: usize = PAGE_SIZE.trailing_zeros() as usize;
 
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -13,6 +15,10 @@ pub struct PhysPageNum(pub usize);
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
 pub struct VirtPageNum(pub usize);
 
+/// Number of page table levels, 3 in Sv39
+pub const PT_LEVELS: usize = TARGET.pt_levels;
+const PT_INDEX_BITS: usize = 9;
+
 const PA_WIDTH_SV39: usize = 56;
 const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
 
@@ -40,13 +46,13 @@ impl From<PhysPageNum> for usize {
 impl PhysAddr {
     pub fn page_offset(&self) -> usize { self.0 & (PAGE_SIZE - 1) }
     pub fn floor(&self) -> PhysPageNum { PhysPageNum(self.0 / PAGE_SIZE) }
-    pub fn ceil(&self) -> PhysPageNum { PhysPageNum((self.0 + PAGE_SIZE - 1) / PAGE_SIZE) }
+    pub fn ceil(&self) -> PhysPageNum { PhysPageNum(self.0.div_ceil(PAGE_SIZE)) }
 }
 
 impl VirtAddr {
     pub fn page_offset(&self) -> usize { self.0 & (PAGE_SIZE - 1) }
     pub fn floor(&self) -> VirtPageNum { VirtPageNum(self.0 / PAGE_SIZE) }
-    pub fn ceil(&self) -> VirtPageNum { VirtPageNum((self.0 + PAGE_SIZE - 1) / PAGE_SIZE) }
+    pub fn ceil(&self) -> VirtPageNum { VirtPageNum(self.0.div_ceil(PAGE_SIZE)) }
 }
 
 impl From<PhysAddr> for PhysPageNum {
@@ -85,4 +91,14 @@ impl VirtPageNum {
     pub fn number(&self) -> usize {
         self.0
     }
+
+    /// Whether this page can be the start of a leaf mapping at the given page table level
+    pub fn aligned_at_level(&self, level: usize) -> bool {
+        self.indexes()[PT_LEVELS - level..].iter().all(|idx| *idx == 0)
+    }
+}
+
+/// Number of 4 KiB pages covered by a single leaf entry at the given page table level
+pub fn level_pages(level: usize) -> usize {
+    1 << (PT_INDEX_BITS * level)
 }
\ No newline at end of file
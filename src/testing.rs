@@ -0,0 +1,610 @@
+//! Test doubles shared by the unit tests: an MMU over identity-mapped frames recording the calls
+//! it receives, and a builder of small ELF files.
+
+// Not every test uses every helper
+#![allow(dead_code)]
+
+use core::ops::Range;
+use alloc::{alloc::{alloc_zeroed, dealloc, Layout}, collections::BTreeMap, vec, vec::Vec};
+
+use crate::loader::{Page, Perm, MMU};
+
+pub const R: Perm = Perm { r: true, w: false, x: false };
+pub const RW: Perm = Perm { r: true, w: true, x: false };
+pub const RX: Perm = Perm { r: true, w: false, x: true };
+pub const RWX: Perm = Perm { r: true, w: true, x: true };
+
+/// Number of frames backing a `MockMmu`
+pub const FRAMES: usize = 2048;
+
+/// Alignment of the first frame, so that huge pages can be carved out of the arena
+const ARENA_ALIGN: usize = 0x200000;
+
+const FRAME_SIZE: usize = 4096;
+
+#[derive(Clone, Copy)]
+pub struct Frame(&'static [u8; FRAME_SIZE]);
+
+impl Page for Frame {
+    fn inner(&self) -> &'static [u8; FRAME_SIZE] {
+        self.0
+    }
+}
+
+/**
+ * A call received by `MockMmu`, in the order it was made
+ */
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Call {
+    Alloc { ppn: usize },
+    AllocAligned { ppn: usize, align: usize },
+    AllocFor { ppn: usize, perm: Perm },
+    MapExisting { ppn: usize, vpn: usize, perm: Perm },
+    MapAtLevel { ppn: usize, vpn: usize, perm: Perm, level: usize },
+    AllocContiguous { ppn: usize, count: usize },
+    MapRange { ppn: usize, vpn: usize, count: usize, perm: Perm },
+}
+
+/**
+ * MMU handing out frames from a heap arena, whose host addresses serve as paddrs, so that the
+ * physical memory the loader writes to is directly readable. Page tables are a map from vpn.
+ */
+pub struct MockMmu {
+    arena: *mut u8,
+
+    /// Index of the next frame handed out by `alloc`
+    next: usize,
+
+    /// Frame and permission of every mapped page, by vpn
+    pub table: BTreeMap<usize, (usize, Perm)>,
+
+    pub calls: Vec<Call>,
+
+    /// Whether `alloc_contiguous` hands out frame runs, which are then mapped by `map_range`
+    pub contiguous: bool,
+}
+
+impl MockMmu {
+    pub fn new() -> Self {
+        let arena = unsafe { alloc_zeroed(Self::layout()) };
+        assert!(!arena.is_null());
+        Self { arena, next: 0, table: BTreeMap::new(), calls: Vec::new(), contiguous: false }
+    }
+
+    fn layout() -> Layout {
+        Layout::from_size_align(FRAMES * FRAME_SIZE, ARENA_ALIGN).unwrap()
+    }
+
+    fn first_ppn(&self) -> usize {
+        self.arena as usize / FRAME_SIZE
+    }
+
+    /// Take `count` contiguous frames whose first ppn is a multiple of `align` frames, without
+    /// recording a call, e.g. to hold a VDSO. Returns the first ppn.
+    pub fn reserve(&mut self, count: usize, align: usize) -> usize {
+        let mut ppn = (self.first_ppn() + self.next).next_multiple_of(align);
+        let idx = ppn - self.first_ppn();
+        assert!(idx + count <= FRAMES, "mock MMU out of frames");
+        self.next = idx + count;
+        ppn = self.first_ppn() + idx;
+        ppn
+    }
+
+    /// Paddr range of `count` frames taken by `reserve`, filled with `content`
+    pub fn physical(&mut self, content: &[u8], align: usize) -> Range<usize> {
+        let pages = content.len().div_ceil(FRAME_SIZE).max(1);
+        let start = self.reserve(pages, align) * FRAME_SIZE;
+        unsafe { core::ptr::copy_nonoverlapping(content.as_ptr(), start as *mut u8, content.len()) };
+        start .. start + content.len()
+    }
+
+    /// Fill every frame not handed out yet with `byte`, as if left over by a previous user
+    pub fn fill_free(&mut self, byte: u8) {
+        let start = self.next * FRAME_SIZE;
+        unsafe { core::ptr::write_bytes(self.arena.add(start), byte, FRAMES * FRAME_SIZE - start) };
+    }
+
+    /// Whether `ppn` is a frame of this MMU
+    pub fn owns(&self, ppn: usize) -> bool {
+        (self.first_ppn() .. self.first_ppn() + FRAMES).contains(&ppn)
+    }
+
+    /// Permission `vaddr` is mapped with
+    pub fn perm(&self, vaddr: usize) -> Option<Perm> {
+        self.table.get(&(vaddr / FRAME_SIZE)).map(|(_, perm)| *perm)
+    }
+
+    /// Frame `vaddr` is mapped onto
+    pub fn ppn(&self, vaddr: usize) -> Option<usize> {
+        self.table.get(&(vaddr / FRAME_SIZE)).map(|(ppn, _)| *ppn)
+    }
+
+    /// Read `len` bytes of the address space at `vaddr`, which must be mapped
+    pub fn read(&self, vaddr: usize, len: usize) -> Vec<u8> {
+        (vaddr .. vaddr + len)
+            .map(|at| unsafe { *(self.translate(at).expect("reading unmapped memory") as *const u8) })
+            .collect()
+    }
+
+    /// Little-endian word at `vaddr`
+    pub fn word(&self, vaddr: usize) -> usize {
+        usize::from_le_bytes(self.read(vaddr, 8).try_into().unwrap())
+    }
+
+    /// Write `bytes` into the address space at `vaddr`, regardless of permissions
+    pub fn write(&mut self, vaddr: usize, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            unsafe { *(self.translate(vaddr + i).expect("writing unmapped memory") as *mut u8) = *byte };
+        }
+    }
+
+    /// Vpns mapped by `MapExisting` calls, in call order
+    pub fn mapped_vpns(&self) -> Vec<usize> {
+        self.calls.iter()
+            .filter_map(|call| match call {
+                Call::MapExisting { vpn, .. } => Some(*vpn),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Drop for MockMmu {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.arena, Self::layout()) };
+    }
+}
+
+impl MMU for MockMmu {
+    type AllocatedPage = Frame;
+
+    fn alloc(&mut self) -> Frame {
+        let ppn = self.reserve(1, 1);
+        self.calls.push(Call::Alloc { ppn });
+        Frame(unsafe { &*((ppn * FRAME_SIZE) as *const [u8; FRAME_SIZE]) })
+    }
+
+    fn alloc_aligned(&mut self, align: usize) -> Frame {
+        let ppn = self.reserve(1, (align / FRAME_SIZE).max(1));
+        self.calls.push(Call::AllocAligned { ppn, align });
+        Frame(unsafe { &*((ppn * FRAME_SIZE) as *const [u8; FRAME_SIZE]) })
+    }
+
+    fn alloc_for(&mut self, perm: Perm) -> Frame {
+        let ppn = self.reserve(1, 1);
+        self.calls.push(Call::AllocFor { ppn, perm });
+        Frame(unsafe { &*((ppn * FRAME_SIZE) as *const [u8; FRAME_SIZE]) })
+    }
+
+    fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        self.calls.push(Call::MapExisting { ppn, vpn, perm });
+        self.table.insert(vpn, (ppn, perm));
+    }
+
+    fn map_at_level(&mut self, ppn: usize, vpn: usize, perm: Perm, level: usize) {
+        self.calls.push(Call::MapAtLevel { ppn, vpn, perm, level });
+        for i in 0 .. 1 << (9 * level) {
+            self.table.insert(vpn + i, (ppn + i, perm));
+        }
+    }
+
+    fn supports_huge_pages(&self) -> bool {
+        true
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<Vec<Frame>> {
+        if !self.contiguous {
+            return None;
+        }
+        let ppn = self.reserve(count, 1);
+        self.calls.push(Call::AllocContiguous { ppn, count });
+        Some((ppn .. ppn + count).map(|ppn| Frame(unsafe { &*((ppn * FRAME_SIZE) as *const [u8; FRAME_SIZE]) })).collect())
+    }
+
+    fn map_range(&mut self, ppn: usize, vpn: usize, count: usize, perm: Perm) {
+        self.calls.push(Call::MapRange { ppn, vpn, count, perm });
+        for i in 0 .. count {
+            self.table.insert(vpn + i, (ppn + i, perm));
+        }
+    }
+
+    fn translate(&self, vaddr: usize) -> Option<usize> {
+        self.table.get(&(vaddr / FRAME_SIZE)).map(|(ppn, _)| ppn * FRAME_SIZE + vaddr % FRAME_SIZE)
+    }
+}
+
+pub const ET_REL: u16 = 1;
+pub const ET_EXEC: u16 = 2;
+pub const ET_DYN: u16 = 3;
+
+pub const PT_LOAD: u32 = 1;
+pub const PT_DYNAMIC: u32 = 2;
+pub const PT_INTERP: u32 = 3;
+pub const PT_NOTE: u32 = 4;
+pub const PT_PHDR: u32 = 6;
+pub const PT_TLS: u32 = 7;
+pub const PT_GNU_RELRO: u32 = 0x6474e552;
+
+pub const PF_X: u32 = 1;
+pub const PF_W: u32 = 2;
+pub const PF_R: u32 = 4;
+
+pub const SHT_PROGBITS: u32 = 1;
+pub const SHT_SYMTAB: u32 = 2;
+pub const SHT_STRTAB: u32 = 3;
+pub const SHT_RELA: u32 = 4;
+pub const SHT_DYNAMIC: u32 = 6;
+pub const SHT_NOBITS: u32 = 8;
+pub const SHT_DYNSYM: u32 = 11;
+
+pub const SHF_WRITE: u64 = 0x1;
+pub const SHF_ALLOC: u64 = 0x2;
+pub const SHF_EXECINSTR: u64 = 0x4;
+pub const SHF_TLS: u64 = 0x400;
+
+pub const STB_LOCAL: u8 = 0;
+pub const STB_GLOBAL: u8 = 1;
+pub const STB_WEAK: u8 = 2;
+
+pub const DT_NEEDED: isize = 1;
+pub const DT_PLTRELSZ: isize = 2;
+pub const DT_PLTGOT: isize = 3;
+pub const DT_HASH: isize = 4;
+pub const DT_STRTAB: isize = 5;
+pub const DT_SYMTAB: isize = 6;
+pub const DT_RELA: isize = 7;
+pub const DT_RELASZ: isize = 8;
+pub const DT_RELAENT: isize = 9;
+pub const DT_INIT: isize = 12;
+pub const DT_SONAME: isize = 14;
+pub const DT_REL: isize = 17;
+pub const DT_RELSZ: isize = 18;
+pub const DT_RELENT: isize = 19;
+pub const DT_PLTREL: isize = 20;
+pub const DT_JMPREL: isize = 23;
+pub const DT_INIT_ARRAY: isize = 25;
+pub const DT_INIT_ARRAYSZ: isize = 27;
+pub const DT_FLAGS: isize = 30;
+pub const DT_FLAGS_1: isize = 0x6ffffffb;
+
+/// Section index given to symbols defined by a builder's objects, which need not be real
+pub const SHN_TEXT: u16 = 1;
+
+#[derive(Clone, Debug)]
+pub struct ProgramHeader {
+    pub ty: u32,
+    pub flags: u32,
+    pub offset: usize,
+    pub vaddr: usize,
+    pub filesz: usize,
+    pub memsz: usize,
+    pub align: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SectionHeader {
+    pub name: Vec<u8>,
+    pub ty: u32,
+    pub flags: u64,
+    pub addr: usize,
+    pub offset: usize,
+    pub size: usize,
+    pub link: u32,
+    pub info: u32,
+    pub align: usize,
+    pub entsize: usize,
+}
+
+/**
+ * Builder of little-endian ELF64 RISC-V files. The headers take the first page of the file, and
+ * the content of each segment is appended in turn, at an offset congruent to its vaddr.
+ *
+ * Dynamic symbols, relocations and extra dynamic entries are laid out by `build` in a writable
+ * segment of their own above every other, together with PT_DYNAMIC and their sections.
+ */
+pub struct ElfBuilder {
+    pub elf_type: u16,
+    pub entry: usize,
+    pub flags: u32,
+    pub file: Vec<u8>,
+    pub phdrs: Vec<ProgramHeader>,
+    pub sections: Vec<SectionHeader>,
+
+    /// Emit the section header table
+    pub section_headers: bool,
+
+    /// Dynamic symbols as `(name, value, info, shndx)`, after the null symbol
+    pub syms: Vec<(Vec<u8>, usize, u8, u16)>,
+
+    /// Dynamic relocations as `(offset, type, symbol index, addend)`
+    pub relas: Vec<(usize, usize, usize, usize)>,
+
+    /// PLT relocations, referenced by DT_JMPREL, in the same format as `relas`
+    pub plt_relas: Vec<(usize, usize, usize, usize)>,
+
+    /// Dynamic entries besides those describing the tables above
+    pub dyn_entries: Vec<(isize, usize)>,
+
+    /// Strings of the dynamic string table, other than symbol names
+    pub strings: Vec<Vec<u8>>,
+
+    /// Vaddr of the segment holding the dynamic tables, placed above the others if `None`
+    pub dyn_vaddr: Option<usize>,
+}
+
+impl ElfBuilder {
+    pub fn new(elf_type: u16) -> Self {
+        Self {
+            elf_type,
+            entry: 0,
+            flags: 0,
+            file: vec![0; FRAME_SIZE],
+            phdrs: Vec::new(),
+            sections: Vec::new(),
+            section_headers: true,
+            syms: Vec::new(),
+            relas: Vec::new(),
+            plt_relas: Vec::new(),
+            dyn_entries: Vec::new(),
+            strings: Vec::new(),
+            dyn_vaddr: None,
+        }
+    }
+
+    /// Append `data` to the file, at an offset congruent to `vaddr` modulo a page. Returns the offset.
+    pub fn append(&mut self, vaddr: usize, data: &[u8]) -> usize {
+        let offset = self.file.len().next_multiple_of(FRAME_SIZE) + vaddr % FRAME_SIZE;
+        self.file.resize(offset, 0);
+        self.file.extend_from_slice(data);
+        offset
+    }
+
+    /// Add a PT_LOAD segment at `vaddr` holding `data`, zero-filled up to `memsz`
+    pub fn load(&mut self, vaddr: usize, flags: u32, data: &[u8], memsz: usize) -> &mut ProgramHeader {
+        let offset = self.append(vaddr, data);
+        self.phdrs.push(ProgramHeader { ty: PT_LOAD, flags, offset, vaddr, filesz: data.len(), memsz, align: FRAME_SIZE });
+        self.phdrs.last_mut().unwrap()
+    }
+
+    /// Add a program header of type `ty` over the file content of the loaded `vaddr .. vaddr + memsz`
+    pub fn phdr(&mut self, ty: u32, flags: u32, vaddr: usize, filesz: usize, memsz: usize) -> &mut ProgramHeader {
+        let offset = self.offset_of(vaddr).unwrap_or(0);
+        self.phdrs.push(ProgramHeader { ty, flags, offset, vaddr, filesz, memsz, align: 8 });
+        self.phdrs.last_mut().unwrap()
+    }
+
+    /// Add a section over the loaded `addr .. addr + size`, with no file content for NOBITS ones
+    pub fn section(&mut self, name: &[u8], ty: u32, flags: u64, addr: usize, size: usize) -> &mut SectionHeader {
+        let offset = if ty == SHT_NOBITS { 0 } else { self.offset_of(addr).expect("section outside of segments") };
+        self.sections.push(SectionHeader { name: name.to_vec(), ty, flags, addr, offset, size, link: 0, info: 0, align: 1, entsize: 0 });
+        self.sections.last_mut().unwrap()
+    }
+
+    /// Append a section not covered by segments, e.g. of a relocatable object. Returns its index.
+    pub fn raw_section(&mut self, name: &[u8], ty: u32, flags: u64, data: &[u8]) -> usize {
+        let offset = self.file.len().next_multiple_of(8);
+        self.file.resize(offset, 0);
+        if ty != SHT_NOBITS {
+            self.file.extend_from_slice(data);
+        }
+        self.sections.push(SectionHeader { name: name.to_vec(), ty, flags, addr: 0, offset, size: data.len(), link: 0, info: 0, align: 8, entsize: 0 });
+        self.sections.len()
+    }
+
+    /// File offset of a vaddr inside the file content of a PT_LOAD segment
+    pub fn offset_of(&self, vaddr: usize) -> Option<usize> {
+        self.phdrs.iter()
+            .find(|ph| ph.ty == PT_LOAD && (ph.vaddr .. ph.vaddr + ph.filesz).contains(&vaddr))
+            .map(|ph| ph.offset + (vaddr - ph.vaddr))
+    }
+
+    /// Overwrite file content at a loaded vaddr
+    pub fn write(&mut self, vaddr: usize, bytes: &[u8]) {
+        let offset = self.offset_of(vaddr).expect("write outside of segments");
+        self.file[offset .. offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Add a dynamic symbol, returning its index
+    pub fn sym(&mut self, name: &[u8], value: usize, bind: u8, shndx: u16) -> usize {
+        self.syms.push((name.to_vec(), value, bind << 4, shndx));
+        self.syms.len()
+    }
+
+    pub fn rela(&mut self, offset: usize, ty: usize, sym: usize, addend: usize) {
+        self.relas.push((offset, ty, sym, addend));
+    }
+
+    pub fn plt_rela(&mut self, offset: usize, ty: usize, sym: usize, addend: usize) {
+        self.plt_relas.push((offset, ty, sym, addend));
+    }
+
+    /// Add a string to the dynamic string table, returning its offset in the built table
+    pub fn string(&mut self, string: &[u8]) -> usize {
+        self.strings.push(string.to_vec());
+        self.dynstr().1[self.strings.len() - 1]
+    }
+
+    /// The dynamic string table, with the offsets of `strings` and of symbol names
+    fn dynstr(&self) -> (Vec<u8>, Vec<usize>, Vec<usize>) {
+        let mut table = vec![0];
+        let mut add = |string: &[u8]| {
+            let offset = table.len();
+            table.extend_from_slice(string);
+            table.push(0);
+            offset
+        };
+        let strings = self.strings.iter().map(|string| add(string)).collect();
+        let names = self.syms.iter().map(|(name, ..)| add(name)).collect();
+        (table, strings, names)
+    }
+
+    fn has_dynamic(&self) -> bool {
+        !self.syms.is_empty() || !self.relas.is_empty() || !self.plt_relas.is_empty() || !self.dyn_entries.is_empty() || self.dyn_vaddr.is_some()
+    }
+
+    /// Lay out the dynamic tables and array in a segment of their own
+    fn build_dynamic(&mut self) {
+        let vaddr = self.dyn_vaddr.unwrap_or_else(|| {
+            let end = self.phdrs.iter().filter(|ph| ph.ty == PT_LOAD).map(|ph| ph.vaddr + ph.memsz).max().unwrap_or(0);
+            end.next_multiple_of(FRAME_SIZE).max(FRAME_SIZE)
+        });
+
+        let mut data = vec![0; 24];
+        for (_, value, info, shndx) in self.syms.iter() {
+            let mut sym = [0u8; 24];
+            sym[4] = *info;
+            sym[6 .. 8].copy_from_slice(&shndx.to_le_bytes());
+            sym[8 .. 16].copy_from_slice(&(*value as u64).to_le_bytes());
+            data.extend_from_slice(&sym);
+        }
+        let (dynstr, _, names) = self.dynstr();
+        for (idx, name) in names.iter().enumerate() {
+            let at = 24 * (idx + 1);
+            data[at .. at + 4].copy_from_slice(&(*name as u32).to_le_bytes());
+        }
+        let dynsym = 0 .. data.len();
+        data.extend_from_slice(&dynstr);
+        let dynstr = dynsym.end .. data.len();
+
+        data.resize(data.len().next_multiple_of(8), 0);
+        let mut table = |relas: &[(usize, usize, usize, usize)]| {
+            let start = data.len();
+            for (offset, ty, sym, addend) in relas {
+                for field in [*offset, (sym << 32) | ty, *addend] {
+                    data.extend_from_slice(&(field as u64).to_le_bytes());
+                }
+            }
+            start .. data.len()
+        };
+        let rela = table(&self.relas);
+        let plt_rela = table(&self.plt_relas);
+
+        let mut entries = vec![(DT_SYMTAB, vaddr + dynsym.start), (DT_STRTAB, vaddr + dynstr.start)];
+        if !rela.is_empty() {
+            entries.extend([(DT_RELA, vaddr + rela.start), (DT_RELASZ, rela.len()), (DT_RELAENT, 24)]);
+        }
+        if !plt_rela.is_empty() {
+            entries.extend([(DT_JMPREL, vaddr + plt_rela.start), (DT_PLTRELSZ, plt_rela.len()), (DT_PLTREL, DT_RELA as usize)]);
+        }
+        entries.extend(self.dyn_entries.iter().copied());
+        entries.push((0, 0));
+        let dynamic_start = data.len();
+        for (tag, val) in entries {
+            data.extend_from_slice(&(tag as i64).to_le_bytes());
+            data.extend_from_slice(&(val as u64).to_le_bytes());
+        }
+        let dynamic = dynamic_start .. data.len();
+
+        self.load(vaddr, PF_R | PF_W, &data, data.len());
+        self.phdr(PT_DYNAMIC, PF_R | PF_W, vaddr + dynamic.start, dynamic.len(), dynamic.len());
+        let section = |builder: &mut Self, name: &[u8], ty: u32, range: &Range<usize>, entsize: usize| {
+            builder.section(name, ty, SHF_ALLOC | SHF_WRITE, vaddr + range.start, range.len()).entsize = entsize;
+        };
+        section(self, b".dynsym", SHT_DYNSYM, &dynsym, 24);
+        section(self, b".dynstr", SHT_STRTAB, &dynstr, 0);
+        if !rela.is_empty() {
+            section(self, b".rela.dyn", SHT_RELA, &rela, 24);
+        }
+        if !plt_rela.is_empty() {
+            section(self, b".rela.plt", SHT_RELA, &plt_rela, 24);
+        }
+        section(self, b".dynamic", SHT_DYNAMIC, &dynamic, 16);
+    }
+
+    pub fn build(mut self) -> Vec<u8> {
+        if self.has_dynamic() {
+            self.build_dynamic();
+        }
+
+        let mut file = core::mem::take(&mut self.file);
+        let put = |file: &mut Vec<u8>, at: usize, bytes: &[u8]| file[at .. at + bytes.len()].copy_from_slice(bytes);
+        assert!(64 + 56 * self.phdrs.len() <= FRAME_SIZE, "too many program headers");
+        for (idx, ph) in self.phdrs.iter().enumerate() {
+            let at = 64 + 56 * idx;
+            put(&mut file, at, &ph.ty.to_le_bytes());
+            put(&mut file, at + 4, &ph.flags.to_le_bytes());
+            for (field, value) in [ph.offset, ph.vaddr, ph.vaddr, ph.filesz, ph.memsz, ph.align].iter().enumerate() {
+                put(&mut file, at + 8 + 8 * field, &(*value as u64).to_le_bytes());
+            }
+        }
+
+        let (mut shoff, mut shnum, mut shstrndx) = (0, 0, 0);
+        if self.section_headers {
+            let mut shstrtab = vec![0];
+            let mut names = Vec::new();
+            for name in self.sections.iter().map(|sec| sec.name.clone()).chain([b".shstrtab".to_vec()]) {
+                names.push(shstrtab.len());
+                shstrtab.extend_from_slice(&name);
+                shstrtab.push(0);
+            }
+            let shstrtab_offset = file.len();
+            file.extend_from_slice(&shstrtab);
+            let shstrtab = SectionHeader {
+                name: Vec::new(), ty: SHT_STRTAB, flags: 0, addr: 0, offset: shstrtab_offset, size: shstrtab.len(),
+                link: 0, info: 0, align: 1, entsize: 0,
+            };
+
+            file.resize(file.len().next_multiple_of(8), 0);
+            shoff = file.len();
+            file.extend_from_slice(&[0; 64]);
+            for (sec, name) in self.sections.iter().chain([&shstrtab]).zip(names) {
+                let mut hdr = [0u8; 64];
+                hdr[0 .. 4].copy_from_slice(&(name as u32).to_le_bytes());
+                hdr[4 .. 8].copy_from_slice(&sec.ty.to_le_bytes());
+                hdr[8 .. 16].copy_from_slice(&sec.flags.to_le_bytes());
+                for (field, value) in [sec.addr, sec.offset, sec.size].iter().enumerate() {
+                    hdr[16 + 8 * field .. 24 + 8 * field].copy_from_slice(&(*value as u64).to_le_bytes());
+                }
+                hdr[40 .. 44].copy_from_slice(&sec.link.to_le_bytes());
+                hdr[44 .. 48].copy_from_slice(&sec.info.to_le_bytes());
+                hdr[48 .. 56].copy_from_slice(&(sec.align as u64).to_le_bytes());
+                hdr[56 .. 64].copy_from_slice(&(sec.entsize as u64).to_le_bytes());
+                file.extend_from_slice(&hdr);
+            }
+            shnum = self.sections.len() + 2;
+            shstrndx = shnum - 1;
+        }
+
+        put(&mut file, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut file, 16, &self.elf_type.to_le_bytes());
+        put(&mut file, 18, &243u16.to_le_bytes());
+        put(&mut file, 20, &1u32.to_le_bytes());
+        put(&mut file, 24, &(self.entry as u64).to_le_bytes());
+        put(&mut file, 32, &64u64.to_le_bytes());
+        put(&mut file, 40, &(shoff as u64).to_le_bytes());
+        put(&mut file, 48, &self.flags.to_le_bytes());
+        put(&mut file, 52, &64u16.to_le_bytes());
+        put(&mut file, 54, &56u16.to_le_bytes());
+        put(&mut file, 56, &(self.phdrs.len() as u16).to_le_bytes());
+        put(&mut file, 58, &64u16.to_le_bytes());
+        put(&mut file, 60, &(shnum as u16).to_le_bytes());
+        put(&mut file, 62, &(shstrndx as u16).to_le_bytes());
+        file
+    }
+}
+
+/// Symbol and string tables holding `(name, value, info, shndx)` symbols after the null symbol
+pub fn symtab(syms: &[(&[u8], usize, u8, u16)]) -> (Vec<u8>, Vec<u8>) {
+    let mut symtab = vec![0; 24];
+    let mut strtab = vec![0];
+    for (name, value, info, shndx) in syms {
+        let name_offset = if name.is_empty() { 0 } else { strtab.len() };
+        strtab.extend_from_slice(name);
+        strtab.push(0);
+        symtab.extend_from_slice(&(name_offset as u32).to_le_bytes());
+        symtab.push(*info);
+        symtab.push(0);
+        symtab.extend_from_slice(&shndx.to_le_bytes());
+        symtab.extend_from_slice(&(*value as u64).to_le_bytes());
+        symtab.extend_from_slice(&0u64.to_le_bytes());
+    }
+    (symtab, strtab)
+}
+
+/// Relocation table holding `(offset, type, symbol index, addend)` entries
+pub fn rela_table(relas: &[(usize, usize, usize, usize)]) -> Vec<u8> {
+    relas.iter()
+        .flat_map(|(offset, ty, sym, addend)| [*offset, (sym << 32) | ty, *addend])
+        .flat_map(|field| (field as u64).to_le_bytes())
+        .collect()
+}
@@ -0,0 +1,103 @@
+use core::fmt::Write;
+
+use crate::{elf::{Dynamic, FileMap, Header, RelTable}, linker::{read_field, reloc_width}, loader::{Loader, Page, Perm, MMU}, mem::{PhysAddr, VirtAddr}};
+
+/**
+ * MMU forwarding to another one, while writing a line per mapped page to `out`, e.g.
+ * `map vpn=0x101 <- ppn=0x80123 r-x`. Errors of `out` are ignored.
+ */
+pub struct Trace<'a, M: MMU> {
+    pub mmu: &'a mut M,
+    pub out: &'a mut dyn Write,
+}
+
+impl<'a, M: MMU> Trace<'a, M> {
+    pub fn new(mmu: &'a mut M, out: &'a mut dyn Write) -> Self {
+        Self { mmu, out }
+    }
+
+    fn map_line(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        let _ = writeln!(self.out, "map vpn={:#x} <- ppn={:#x} {}", vpn, ppn, perm);
+    }
+
+    /**
+     * Write a line per dynamic relocation of `buf` applied by `loader`, e.g.
+     * `reloc +0x3fe0 = 0x1011a0 (puts)`, with the offset in link-time vaddrs and the value read
+     * back from memory. Relocations deferred by lazy relocation and not applied yet are skipped.
+     */
+    pub fn relocations(&mut self, buf: &[u8], loader: &Loader) {
+        let Some(header) = Header::parse(buf) else { return };
+        let Some(range) = header.dynamic() else { return };
+        let Some(dynamic) = Dynamic::parse(buf, range, &FileMap::new(&header.phdrs)) else { return };
+        if let Some(RelTable::REL(_)) = &dynamic.rel {
+            return;
+        }
+
+        for ent in dynamic.relas() {
+            let vaddr = loader.bias.wrapping_add(ent.offset);
+            if ent.reloc_type() == 0 || loader.is_pending(VirtAddr(vaddr).floor().0) {
+                continue;
+            }
+            let Some(paddr) = self.mmu.translate(vaddr) else { continue };
+            let value = unsafe { read_field(paddr, reloc_width(ent.reloc_type())) };
+            let _ = write!(self.out, "reloc +{:#x} = {:#x}", ent.offset, value);
+            if let Some((_, name)) = dynamic.resolve_sym(ent.sym_index()).filter(|(_, name)| !name.is_empty()) {
+                let _ = write!(self.out, " ({})", core::str::from_utf8(name).unwrap_or("?"));
+            }
+            let _ = writeln!(self.out);
+        }
+    }
+}
+
+impl<M: MMU> MMU for Trace<'_, M> {
+    type AllocatedPage = M::AllocatedPage;
+
+    fn alloc(&mut self) -> Self::AllocatedPage {
+        self.mmu.alloc()
+    }
+
+    fn alloc_aligned(&mut self, align: usize) -> Self::AllocatedPage {
+        self.mmu.alloc_aligned(align)
+    }
+
+    fn alloc_for(&mut self, perm: Perm) -> Self::AllocatedPage {
+        self.mmu.alloc_for(perm)
+    }
+
+    fn map(&mut self, page: Self::AllocatedPage, vpn: usize, perm: Perm) {
+        self.map_line(PhysAddr(page.inner() as *const u8 as usize).floor().0, vpn, perm);
+        self.mmu.map(page, vpn, perm)
+    }
+
+    fn map_existing(&mut self, ppn: usize, vpn: usize, perm: Perm) {
+        self.map_line(ppn, vpn, perm);
+        self.mmu.map_existing(ppn, vpn, perm)
+    }
+
+    fn supports_huge_pages(&self) -> bool {
+        self.mmu.supports_huge_pages()
+    }
+
+    fn map_at_level(&mut self, ppn: usize, vpn: usize, perm: Perm, level: usize) {
+        let _ = writeln!(self.out, "map vpn={:#x} <- ppn={:#x} {} level={}", vpn, ppn, perm, level);
+        self.mmu.map_at_level(ppn, vpn, perm, level)
+    }
+
+    fn alloc_contiguous(&mut self, count: usize) -> Option<alloc::vec::Vec<Self::AllocatedPage>> {
+        self.mmu.alloc_contiguous(count)
+    }
+
+    fn map_range(&mut self, ppn: usize, vpn: usize, count: usize, perm: Perm) {
+        for i in 0 .. count {
+            self.map_line(ppn + i, vpn + i, perm);
+        }
+        self.mmu.map_range(ppn, vpn, count, perm)
+    }
+
+    fn translate(&self, vaddr: usize) -> Option<usize> {
+        self.mmu.translate(vaddr)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,67 @@
+use super::*;
+use alloc::string::String;
+use crate::{elf::{R_RISCV_64, R_RISCV_RELATIVE}, loader::{LoadOptions, StackConfig, VDSOConfig}, testing::*};
+
+type Lookup = fn(&[u8]) -> Option<usize>;
+
+#[test]
+fn trace_lists_mappings_and_relocations() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 16], 16);
+    let exit = builder.sym(b"exit", 0x3000, STB_GLOBAL, SHN_TEXT);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    builder.rela(0x3008, R_RISCV_64, exit, 0);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let mut out = String::new();
+    let mut trace = Trace::new(&mut mmu, &mut out);
+    let ldso: Option<VDSOConfig<Lookup>> = None;
+    let loader = Loader::load_segments(&buf, &mut trace, 0x10_0000, ldso, StackConfig::fixed(0x8000, 0x9000), LoadOptions::default()).unwrap();
+    trace.relocations(&buf, &loader);
+
+    let ppn = mmu.ppn(0x10_3000).unwrap();
+    assert!(out.contains(&alloc::format!("map vpn=0x103 <- ppn={:#x} rw-\n", ppn)));
+    assert!(out.contains("reloc +0x3000 = 0x100010\n"));
+    assert!(out.contains("reloc +0x3008 = 0x103000 (exit)\n"));
+}
+
+#[test]
+fn trace_includes_lazily_applied_relocations() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let mut out = String::new();
+    let mut trace = Trace::new(&mut mmu, &mut out);
+    let ldso: Option<VDSOConfig<Lookup>> = None;
+    let options = LoadOptions { lazy_relocation: true, ..LoadOptions::default() };
+    let mut loader = Loader::load_segments(&buf, &mut trace, 0x10_0000, ldso, StackConfig::fixed(0x8000, 0x9000), options).unwrap();
+    trace.relocations(&buf, &loader);
+    assert!(loader.relocate_page(&mut trace, 0x10_3000));
+    trace.relocations(&buf, &loader);
+
+    assert_eq!(out.matches("reloc +0x3000 = 0x100010\n").count(), 1);
+}
+
+#[test]
+fn trace_uses_load_bias_regardless_of_entry() {
+    let mut builder = ElfBuilder::new(ET_DYN);
+    builder.entry = 0x1000;
+    builder.load(0x3000, PF_R | PF_W, &[0; 8], 8);
+    builder.rela(0x3000, R_RISCV_RELATIVE, 0, 0x10);
+    let buf = builder.build();
+
+    let mut mmu = MockMmu::new();
+    let mut out = String::new();
+    let mut trace = Trace::new(&mut mmu, &mut out);
+    let ldso: Option<VDSOConfig<Lookup>> = None;
+    let mut loader = Loader::load_segments(&buf, &mut trace, 0x10_0000, ldso, StackConfig::fixed(0x8000, 0x9000), LoadOptions::default()).unwrap();
+    assert_eq!(loader.bias, 0x10_0000);
+    // e.g. started through an interpreter instead
+    loader.entry = 0x4000_0000;
+    trace.relocations(&buf, &loader);
+    assert!(out.contains("reloc +0x3000 = 0x100010\n"));
+}